@@ -0,0 +1,27 @@
+//! Time assembling a large program: `cargo bench --bench parse`.
+
+use risc_v_assembler::assemble;
+use std::time::Instant;
+
+const BLOCK: &str = "1:
+    ld x6, 0(x10)
+    add x5, x5, x6
+    addi x10, x10, 8
+    li x7, 0x12345
+    sd x5, 16(x11)
+    mul x5, x5, x7
+    bne x6, x0, 1b
+";
+
+fn main() {
+    let asm = BLOCK.repeat(10_000);
+    let start = Instant::now();
+    let words = assemble(&asm).unwrap();
+    let elapsed = start.elapsed();
+    println!(
+        "assembled {} lines into {} words in {:?}",
+        asm.lines().count(),
+        words.len(),
+        elapsed
+    );
+}
@@ -0,0 +1,197 @@
+//! Build programs from Rust code instead of assembly source.
+//!
+//! ```
+//! use risc_v_assembler::builder::Assembler;
+//!
+//! let mut asm = Assembler::new();
+//! asm.label("Loop").addi(5, 5, -1).beq(5, 0, "Exit").j("Loop").label("Exit");
+//! assert_eq!(asm.finish().unwrap().len(), 3);
+//! ```
+
+use crate::instruction::{BranchOp, IOp, Immediate, Instruction, LoadOp, ROp, StoreOp};
+use crate::{assemble_statements, AssembleError, Options, Statement};
+
+/// A program under construction. Errors in operands are reported by
+/// `finish`, so calls can be chained.
+#[derive(Debug, Clone, Default)]
+pub struct Assembler {
+    statements: Vec<Statement>,
+    error: Option<AssembleError>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Assembler::default()
+    }
+
+    pub fn nop(&mut self) -> &mut Self {
+        self.push(Instruction::Nop)
+    }
+
+    pub fn add(&mut self, rd: u32, rs1: u32, rs2: u32) -> &mut Self {
+        self.r(ROp::Add, rd, rs1, rs2)
+    }
+
+    pub fn sub(&mut self, rd: u32, rs1: u32, rs2: u32) -> &mut Self {
+        self.r(ROp::Sub, rd, rs1, rs2)
+    }
+
+    pub fn and(&mut self, rd: u32, rs1: u32, rs2: u32) -> &mut Self {
+        self.r(ROp::And, rd, rs1, rs2)
+    }
+
+    pub fn or(&mut self, rd: u32, rs1: u32, rs2: u32) -> &mut Self {
+        self.r(ROp::Or, rd, rs1, rs2)
+    }
+
+    pub fn addi(&mut self, rd: u32, rs1: u32, imm: i32) -> &mut Self {
+        self.check_registers(&[rd, rs1]);
+        let imm = self.imm12(imm);
+        self.push(Instruction::I {
+            op: IOp::Addi,
+            rd,
+            rs1,
+            imm,
+        })
+    }
+
+    /// `ld rd, imm(rs1)`
+    pub fn ld(&mut self, rd: u32, imm: i32, rs1: u32) -> &mut Self {
+        self.check_registers(&[rd, rs1]);
+        let imm = self.imm12(imm);
+        self.push(Instruction::Load {
+            op: LoadOp::Ld,
+            rd,
+            rs1,
+            imm,
+        })
+    }
+
+    /// `sd rs2, imm(rs1)`
+    pub fn sd(&mut self, rs2: u32, imm: i32, rs1: u32) -> &mut Self {
+        self.check_registers(&[rs1, rs2]);
+        let imm = self.imm12(imm);
+        self.push(Instruction::Store {
+            op: StoreOp::Sd,
+            rs1,
+            rs2,
+            imm,
+        })
+    }
+
+    pub fn beq(&mut self, rs1: u32, rs2: u32, label: &str) -> &mut Self {
+        self.branch(BranchOp::Beq, rs1, rs2, label)
+    }
+
+    pub fn bne(&mut self, rs1: u32, rs2: u32, label: &str) -> &mut Self {
+        self.branch(BranchOp::Bne, rs1, rs2, label)
+    }
+
+    pub fn blt(&mut self, rs1: u32, rs2: u32, label: &str) -> &mut Self {
+        self.branch(BranchOp::Blt, rs1, rs2, label)
+    }
+
+    pub fn bge(&mut self, rs1: u32, rs2: u32, label: &str) -> &mut Self {
+        self.branch(BranchOp::Bge, rs1, rs2, label)
+    }
+
+    pub fn j(&mut self, label: &str) -> &mut Self {
+        self.statements
+            .push(Statement::Pseudo(vec![Instruction::Jal {
+                rd: 0,
+                label: label.to_string(),
+            }]));
+        self
+    }
+
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        self.statements.push(Statement::Label(name.to_string()));
+        self
+    }
+
+    /// Resolve labels and encode the program.
+    pub fn finish(&self) -> Result<Vec<u32>, AssembleError> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        let (program, _) = assemble_statements(&self.statements, &Options::default())
+            .map_err(|(_, error)| error)?;
+        Ok(program.instructions)
+    }
+
+    fn push(&mut self, inst: Instruction) -> &mut Self {
+        self.statements.push(Statement::Instruction(inst));
+        self
+    }
+
+    fn r(&mut self, op: ROp, rd: u32, rs1: u32, rs2: u32) -> &mut Self {
+        self.check_registers(&[rd, rs1, rs2]);
+        self.push(Instruction::R { op, rd, rs1, rs2 })
+    }
+
+    fn branch(&mut self, op: BranchOp, rs1: u32, rs2: u32, label: &str) -> &mut Self {
+        self.check_registers(&[rs1, rs2]);
+        self.push(Instruction::Branch {
+            op,
+            rs1,
+            rs2,
+            label: label.to_string(),
+        })
+    }
+
+    /// Record the first error, keeping it over any later one.
+    fn fail(&mut self, error: AssembleError) {
+        self.error.get_or_insert(error);
+    }
+
+    fn check_registers(&mut self, registers: &[u32]) {
+        if let Some(register) = registers.iter().find(|register| **register > 31) {
+            self.fail(AssembleError::InvalidRegister(format!("x{}", register)));
+        }
+    }
+
+    fn imm12(&mut self, imm: i32) -> Immediate {
+        if !(-2048..=2047).contains(&imm) {
+            self.fail(AssembleError::InvalidImmediate(imm.to_string()));
+        }
+        Immediate::Value(imm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn builder() {
+        let mut builder = Assembler::new();
+        builder
+            .label("Loop")
+            .add(5, 5, 6)
+            .ld(7, 8, 5)
+            .sd(7, 16, 2)
+            .beq(5, 6, "Exit")
+            .j("Loop")
+            .label("Exit")
+            .nop();
+        let asm = "Loop:\nadd x5, x5, x6\nld x7, 8(x5)\nsd x7, 16(x2)\nbeq x5, x6, Exit\nj Loop\nExit:\nnop";
+        assert_eq!(builder.finish(), assemble(asm));
+    }
+
+    #[test]
+    fn builder_errors() {
+        let mut builder = Assembler::new();
+        builder.add(32, 0, 0).addi(5, 0, 4096);
+        assert_eq!(
+            builder.finish(),
+            Err(AssembleError::InvalidRegister("x32".to_string()))
+        );
+        let mut builder = Assembler::new();
+        builder.beq(5, 6, "Missing");
+        assert!(matches!(
+            builder.finish(),
+            Err(AssembleError::InvalidLabel { .. })
+        ));
+    }
+}
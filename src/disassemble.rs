@@ -0,0 +1,452 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+pub(crate) const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+pub(crate) const FP_ABI_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegNames {
+    Numeric,
+    Abi,
+}
+
+impl FromStr for RegNames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "numeric" => Ok(RegNames::Numeric),
+            "abi" => Ok(RegNames::Abi),
+            _ => Err(format!(
+                "Invalid Register Names: `{}` (expected `numeric` or `abi`)",
+                s
+            )),
+        }
+    }
+}
+
+fn freg(inst: u32, shift: u32, names: RegNames) -> String {
+    let reg = (inst >> shift) & 0b11111;
+    match names {
+        RegNames::Numeric => format!("f{}", reg),
+        RegNames::Abi => FP_ABI_NAMES[reg as usize].to_string(),
+    }
+}
+
+fn reg(inst: u32, shift: u32, names: RegNames) -> String {
+    let reg = (inst >> shift) & 0b11111;
+    match names {
+        RegNames::Numeric => format!("x{}", reg),
+        RegNames::Abi => ABI_NAMES[reg as usize].to_string(),
+    }
+}
+
+/// The target instruction index of a branch or `jal` at index `i`, or
+/// `None` if the word is neither. Branch offsets use the processor's
+/// encoding: a magnitude in bits 11:7 and a sign in bit 25.
+pub(crate) fn branch_target(i: usize, inst: u32) -> Option<isize> {
+    if inst & 0b1111111 == 0b1101111 {
+        let offset = ((inst & 0x80000000) as i32 >> 11) as u32
+            | (inst & 0x000ff000)
+            | ((inst >> 9) & 0x800)
+            | ((inst >> 20) & 0x7fe);
+        return Some(i as isize + offset as i32 as isize / 4);
+    }
+    if inst & 0b1111111 != 0b1100011 {
+        return None;
+    }
+    let offset = ((inst >> 7) & 0b11111) as isize;
+    if inst & 0b00000010_00000000_00000000_00000000 == 0 {
+        Some(i as isize + offset)
+    } else {
+        Some(i as isize - offset)
+    }
+}
+
+/// A decoded instruction word.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodedInstruction {
+    Known {
+        mnemonic: &'static str,
+        operands: Vec<String>,
+        word: u32,
+    },
+    Unknown(u32),
+}
+
+impl DecodedInstruction {
+    /// The raw instruction word.
+    pub fn word(&self) -> u32 {
+        match self {
+            DecodedInstruction::Known { word, .. } => *word,
+            DecodedInstruction::Unknown(word) => *word,
+        }
+    }
+}
+
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodedInstruction::Known {
+                mnemonic, operands, ..
+            } if operands.is_empty() => write!(f, "{}", mnemonic),
+            DecodedInstruction::Known {
+                mnemonic, operands, ..
+            } => write!(f, "{} {}", mnemonic, operands.join(", ")),
+            DecodedInstruction::Unknown(word) => {
+                write!(f, "// Invalid Instruction: `{:0>32b}`", word)
+            }
+        }
+    }
+}
+
+pub(crate) fn decode_one(i: usize, inst: u32, names: RegNames) -> DecodedInstruction {
+    let opcode = inst & 0b1111111;
+    let funct3 = (inst >> 12) & 0b111;
+    let funct7 = inst >> 25;
+    let rd = || reg(inst, 7, names);
+    let rs1 = || reg(inst, 15, names);
+    let rs2 = || reg(inst, 20, names);
+    let frd = || freg(inst, 7, names);
+    let frs1 = || freg(inst, 15, names);
+    let frs2 = || freg(inst, 20, names);
+    // Every branch and jump has a target, and no other instruction uses it.
+    let target = branch_target(i, inst).unwrap_or_default();
+    let (mnemonic, operands) = match (opcode, funct3, funct7) {
+        _ if inst == 0 => ("nop", vec![]),
+        (0b1110011, 0b000, 0b0001001) if (inst >> 7) & 0b11111 == 0 => {
+            match ((inst >> 15) & 0b11111, (inst >> 20) & 0b11111) {
+                (0, 0) => ("sfence.vma", vec![]),
+                _ => ("sfence.vma", vec![rs1(), rs2()]),
+            }
+        }
+        (0b1110011, _, _) => match inst {
+            0x00000073 => ("ecall", vec![]),
+            0x00100073 => ("ebreak", vec![]),
+            0x10500073 => ("wfi", vec![]),
+            0x30200073 => ("mret", vec![]),
+            0x10200073 => ("sret", vec![]),
+            _ => return DecodedInstruction::Unknown(inst),
+        },
+        _ if inst == 0x0100000f => ("pause", vec![]),
+        (0b0000011, 0b011, _) => (
+            "ld",
+            vec![rd(), format!("{}({})", inst as i32 >> 20, rs1())],
+        ),
+        (0b0000011, 0b010, _) => (
+            "lw",
+            vec![rd(), format!("{}({})", inst as i32 >> 20, rs1())],
+        ),
+        (0b0100011, 0b011, _) => {
+            let imm = ((inst as i32 >> 25) << 5) | ((inst >> 7) & 0b11111) as i32;
+            ("sd", vec![rs2(), format!("{}({})", imm, rs1())])
+        }
+        (0b0000111, 0b010, _) => (
+            "flw",
+            vec![frd(), format!("{}({})", inst as i32 >> 20, rs1())],
+        ),
+        (0b0100111, 0b010, _) => {
+            let imm = ((inst as i32 >> 25) << 5) | ((inst >> 7) & 0b11111) as i32;
+            ("fsw", vec![frs2(), format!("{}({})", imm, rs1())])
+        }
+        (0b1010011, _, 0b0000000 | 0b0000100 | 0b0001000 | 0b0001100) => {
+            let rm = ["rne", "rtz", "rdn", "rup", "rmm", "", "", "dyn"][funct3 as usize];
+            if rm.is_empty() {
+                return DecodedInstruction::Unknown(inst);
+            }
+            let mnemonic = ["fadd.s", "fsub.s", "fmul.s", "fdiv.s"][(funct7 >> 2) as usize];
+            let mut operands = vec![frd(), frs1(), frs2()];
+            if rm != "dyn" {
+                operands.push(rm.to_string());
+            }
+            (mnemonic, operands)
+        }
+        (0b0110011, 0b111, 0) => ("and", vec![rd(), rs1(), rs2()]),
+        (0b0110011, 0b110, 0) => ("or", vec![rd(), rs1(), rs2()]),
+        (0b0110011, 0b000, 0) => ("add", vec![rd(), rs1(), rs2()]),
+        (0b0110011, 0b000, 0b0100000) => ("sub", vec![rd(), rs1(), rs2()]),
+        (0b0110011, 0b010, 0) => ("slt", vec![rd(), rs1(), rs2()]),
+        (0b0110011, 0b011, 0) => ("sltu", vec![rd(), rs1(), rs2()]),
+        (0b0110011, 0b111, 0b0100000) => ("andn", vec![rd(), rs1(), rs2()]),
+        (0b0110011, 0b110, 0b0100000) => ("orn", vec![rd(), rs1(), rs2()]),
+        (0b0110011, 0b100, 0b0100000) => ("xnor", vec![rd(), rs1(), rs2()]),
+        (0b0110011, 0b100..=0b111, 0b0000101) => {
+            let mnemonic = ["min", "minu", "max", "maxu"];
+            (mnemonic[funct3 as usize - 0b100], vec![rd(), rs1(), rs2()])
+        }
+        (0b0010011, 0b001, 0b0110000) => match (inst >> 20) & 0b11111 {
+            0 => ("clz", vec![rd(), rs1()]),
+            1 => ("ctz", vec![rd(), rs1()]),
+            2 => ("cpop", vec![rd(), rs1()]),
+            _ => return DecodedInstruction::Unknown(inst),
+        },
+        (0b0110011, _, 0b0000001) => {
+            let mnemonic = [
+                "mul", "mulh", "mulhsu", "mulhu", "div", "divu", "rem", "remu",
+            ];
+            (mnemonic[funct3 as usize], vec![rd(), rs1(), rs2()])
+        }
+        (0b0010011, 0b000, _) => ("addi", vec![rd(), rs1(), (inst as i32 >> 20).to_string()]),
+        (0b0110111, _, _) => ("lui", vec![rd(), format!("{:#x}", inst >> 12)]),
+        (0b0010111, _, _) => ("auipc", vec![rd(), format!("{:#x}", inst >> 12)]),
+        (0b1100011, 0b000, 0) | (0b1100011, 0b000, 1) => {
+            ("beq", vec![rs1(), rs2(), format!("L{}", target)])
+        }
+        (0b1100011, 0b001, 0) | (0b1100011, 0b001, 1) => {
+            ("bne", vec![rs1(), rs2(), format!("L{}", target)])
+        }
+        (0b1100011, 0b100, 0) | (0b1100011, 0b100, 1) => {
+            ("blt", vec![rs1(), rs2(), format!("L{}", target)])
+        }
+        (0b1100011, 0b101, 0) | (0b1100011, 0b101, 1) => {
+            ("bge", vec![rs1(), rs2(), format!("L{}", target)])
+        }
+        (0b1100011, 0b110, 0) | (0b1100011, 0b110, 1) => {
+            ("bltu", vec![rs1(), rs2(), format!("L{}", target)])
+        }
+        (0b1100011, 0b111, 0) | (0b1100011, 0b111, 1) => {
+            ("bgeu", vec![rs1(), rs2(), format!("L{}", target)])
+        }
+        (0b1101111, _, _) => ("jal", vec![rd(), format!("L{}", target)]),
+        _ => return DecodedInstruction::Unknown(inst),
+    };
+    DecodedInstruction::Known {
+        mnemonic,
+        operands,
+        word: inst,
+    }
+}
+
+/// Lazily decode a stream of instruction words. Branch targets are named
+/// `L<index>` after the index of the target word in the stream.
+pub fn decode(words: impl Iterator<Item = u32>) -> impl Iterator<Item = DecodedInstruction> {
+    words
+        .enumerate()
+        .map(|(i, word)| decode_one(i, word, RegNames::Numeric))
+}
+
+/// Disassemble a program into assembly lines, generating an `L<index>` label
+/// for every branch target.
+pub fn disassemble(instructions: &[u32], names: RegNames) -> Vec<String> {
+    let targets: BTreeSet<isize> = instructions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, inst)| branch_target(i, *inst))
+        .collect();
+    let mut lines = Vec::new();
+    for (i, inst) in instructions.iter().enumerate() {
+        if targets.contains(&(i as isize)) {
+            lines.push(format!("L{}:", i));
+        }
+        lines.push(decode_one(i, *inst, names).to_string());
+    }
+    if targets.contains(&(instructions.len() as isize)) {
+        lines.push(format!("L{}:", instructions.len()));
+    }
+    lines
+}
+
+#[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reg_names() {
+        let add = 0b0000000_00111_00110_000_00101_0110011;
+        assert_eq!(disassemble(&[add], RegNames::Abi), ["add t0, t1, t2"]);
+        assert_eq!(disassemble(&[add], RegNames::Numeric), ["add x5, x6, x7"]);
+    }
+
+    #[test]
+    fn immediates() {
+        let addi = 0b111111111111_00110_000_00101_0010011;
+        let auipc = 0b00010010001101000101_00101_0010111;
+        let lines = disassemble(&[addi, auipc], RegNames::Numeric);
+        assert_eq!(lines, ["addi x5, x6, -1", "auipc x5, 0x12345"]);
+    }
+
+    #[test]
+    fn float() {
+        let words = [0x00012087, 0x00112427, 0x003170d3, 0x08629253];
+        assert_eq!(
+            disassemble(&words, RegNames::Numeric),
+            [
+                "flw f1, 0(x2)",
+                "fsw f1, 8(x2)",
+                "fadd.s f1, f2, f3",
+                "fsub.s f4, f5, f6, rtz"
+            ]
+        );
+        assert_eq!(
+            disassemble(&[0x0005c553], RegNames::Abi),
+            ["fadd.s fa0, fa1, ft0, rmm"]
+        );
+    }
+
+    #[test]
+    fn decode_stream() {
+        let add = 0b0000000_00111_00110_000_00101_0110011;
+        let decoded: Vec<_> = decode([0, add, 0xffffffff, 0x10500073].iter().copied()).collect();
+        assert_eq!(
+            decoded,
+            [
+                DecodedInstruction::Known {
+                    mnemonic: "nop",
+                    operands: vec![],
+                    word: 0
+                },
+                DecodedInstruction::Known {
+                    mnemonic: "add",
+                    operands: vec!["x5".to_string(), "x6".to_string(), "x7".to_string()],
+                    word: add
+                },
+                DecodedInstruction::Unknown(0xffffffff),
+                DecodedInstruction::Known {
+                    mnemonic: "wfi",
+                    operands: vec![],
+                    word: 0x10500073
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn jal() {
+        let lines = disassemble(&[0xff9ff0ef, 0x0080006f, 0, 0], RegNames::Abi);
+        assert_eq!(lines, ["jal ra, L-2", "jal zero, L3", "nop", "L3:", "nop"]);
+    }
+
+    /// A xorshift generator, so the round-trip test is reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u32) -> u32 {
+            (self.next() % n as u64) as u32
+        }
+
+        fn reg(&mut self) -> u32 {
+            self.below(32)
+        }
+
+        fn imm12(&mut self) -> i32 {
+            self.below(4096) as i32 - 2048
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        use crate::instruction::{BranchOp, IOp, Immediate, Instruction, LoadOp, ROp, StoreOp};
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let i = 64;
+        for _ in 0..10_000 {
+            let (inst, offset, operands) = match rng.below(5) {
+                0 => {
+                    let ops = [
+                        ROp::And,
+                        ROp::Or,
+                        ROp::Add,
+                        ROp::Sub,
+                        ROp::Slt,
+                        ROp::Sltu,
+                        ROp::Mul,
+                        ROp::Mulh,
+                        ROp::Mulhsu,
+                        ROp::Mulhu,
+                        ROp::Div,
+                        ROp::Divu,
+                        ROp::Rem,
+                        ROp::Remu,
+                    ];
+                    let op = ops[rng.below(ops.len() as u32) as usize];
+                    let (rd, rs1, rs2) = (rng.reg(), rng.reg(), rng.reg());
+                    let operands =
+                        vec![format!("x{}", rd), format!("x{}", rs1), format!("x{}", rs2)];
+                    (Instruction::R { op, rd, rs1, rs2 }, 0, operands)
+                }
+                1 => {
+                    let (rd, rs1, imm) = (rng.reg(), rng.reg(), rng.imm12());
+                    let operands = vec![format!("x{}", rd), format!("x{}", rs1), imm.to_string()];
+                    let imm = Immediate::Value(imm);
+                    let op = IOp::Addi;
+                    (Instruction::I { op, rd, rs1, imm }, 0, operands)
+                }
+                2 => {
+                    let (rd, rs1, imm) = (rng.reg(), rng.reg(), rng.imm12());
+                    let operands = vec![format!("x{}", rd), format!("{}(x{})", imm, rs1)];
+                    let imm = Immediate::Value(imm);
+                    let op = LoadOp::Ld;
+                    (Instruction::Load { op, rd, rs1, imm }, 0, operands)
+                }
+                3 => {
+                    let (rs1, rs2, imm) = (rng.reg(), rng.reg(), rng.imm12());
+                    let operands = vec![format!("x{}", rs2), format!("{}(x{})", imm, rs1)];
+                    let imm = Immediate::Value(imm);
+                    let op = StoreOp::Sd;
+                    (Instruction::Store { op, rs1, rs2, imm }, 0, operands)
+                }
+                _ => {
+                    let ops = [
+                        BranchOp::Beq,
+                        BranchOp::Bne,
+                        BranchOp::Blt,
+                        BranchOp::Bge,
+                        BranchOp::Bltu,
+                        BranchOp::Bgeu,
+                    ];
+                    let op = ops[rng.below(6) as usize];
+                    let (rs1, rs2) = (rng.reg(), rng.reg());
+                    let distance = rng.below(63) as isize - 31;
+                    let target = format!("L{}", i as isize + distance);
+                    let operands = vec![format!("x{}", rs1), format!("x{}", rs2), target];
+                    let label = String::new();
+                    let inst = Instruction::Branch {
+                        op,
+                        rs1,
+                        rs2,
+                        label,
+                    };
+                    (inst, distance, operands)
+                }
+            };
+            let mut word = inst.encode();
+            if let Instruction::Branch { .. } = inst {
+                word |= (offset.unsigned_abs() as u32) << 7;
+                if offset <= 0 {
+                    word |= 1 << 25;
+                }
+            }
+            let decoded = decode_one(i, word, RegNames::Numeric);
+            let expected = DecodedInstruction::Known {
+                mnemonic: inst.mnemonic(),
+                operands,
+                word,
+            };
+            assert_eq!(decoded, expected, "{:?} encoded as {:#010x}", inst, word);
+        }
+    }
+
+    #[test]
+    fn labels() {
+        let blt = 0b0000001_00110_00101_100_00010_1100011;
+        let lines = disassemble(&[0, 0, blt], RegNames::Numeric);
+        assert_eq!(lines, ["L0:", "nop", "nop", "blt x5, x6, L0"]);
+    }
+}
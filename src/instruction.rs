@@ -0,0 +1,591 @@
+/// A reference to a label that is patched into an instruction once all
+/// labels are known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Relocation {
+    Branch(String),
+    /// The J-type byte offset of a `jal` to a label.
+    Jump(String),
+    /// The upper 20 bits of the PC-relative offset to a label.
+    PcrelHi(String),
+    /// The lower 12 bits of the PC-relative offset computed by the `auipc`
+    /// at the given label, which must carry a `PcrelHi` relocation.
+    PcrelLo(String),
+    /// The lower 12 bits of the PC-relative offset to a label from the
+    /// instruction before, the `auipc` that `lla` expands to.
+    PcrelPairLo(String),
+    /// The upper 20 bits of a label's absolute address, rounded so that
+    /// adding the sign-extended `Lo` part gives the address.
+    Hi(String),
+    /// The lower 12 bits of a label's absolute address.
+    Lo(String),
+}
+
+impl Relocation {
+    pub fn label(&self) -> &str {
+        match self {
+            Relocation::Branch(label)
+            | Relocation::Jump(label)
+            | Relocation::PcrelHi(label)
+            | Relocation::PcrelLo(label)
+            | Relocation::PcrelPairLo(label)
+            | Relocation::Hi(label)
+            | Relocation::Lo(label) => label,
+        }
+    }
+}
+
+/// An immediate operand, either known at parse time or filled in by a
+/// relocation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Immediate {
+    Value(i32),
+    Relocation(Relocation),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ROp {
+    And,
+    Or,
+    Add,
+    Sub,
+    Slt,
+    Sltu,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+    // Zbb.
+    Andn,
+    Orn,
+    Xnor,
+    Min,
+    Minu,
+    Max,
+    Maxu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IOp {
+    Addi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadOp {
+    Ld,
+    Lw,
+    /// Load a single-precision float into a floating-point register.
+    Flw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreOp {
+    Sd,
+    /// Store a single-precision float from a floating-point register.
+    Fsw,
+}
+
+/// The funct3 width field the ISA manual documents for each supported
+/// load and store mnemonic, checked against the encoder by the tests.
+pub const MEMORY_FUNCT3: &[(&str, u32)] = &[
+    ("lw", 0b010),
+    ("ld", 0b011),
+    ("sd", 0b011),
+    ("flw", 0b010),
+    ("fsw", 0b010),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchOp {
+    Beq,
+    Bne,
+    Blt,
+    Bge,
+    Bltu,
+    Bgeu,
+}
+
+impl BranchOp {
+    /// The branch taken exactly when this one is not.
+    pub fn inverse(self) -> BranchOp {
+        match self {
+            BranchOp::Beq => BranchOp::Bne,
+            BranchOp::Bne => BranchOp::Beq,
+            BranchOp::Blt => BranchOp::Bge,
+            BranchOp::Bge => BranchOp::Blt,
+            BranchOp::Bltu => BranchOp::Bgeu,
+            BranchOp::Bgeu => BranchOp::Bltu,
+        }
+    }
+}
+
+/// A single-precision float operation on floating-point registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FOp {
+    Fadd,
+    Fsub,
+    Fmul,
+    Fdiv,
+}
+
+/// The rounding mode of a float operation, encoded in its funct3 field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    Rne,
+    Rtz,
+    Rdn,
+    Rup,
+    Rmm,
+    /// Round as the `frm` CSR selects.
+    Dyn,
+}
+
+impl RoundingMode {
+    pub fn funct3(self) -> u32 {
+        match self {
+            RoundingMode::Rne => 0b000,
+            RoundingMode::Rtz => 0b001,
+            RoundingMode::Rdn => 0b010,
+            RoundingMode::Rup => 0b011,
+            RoundingMode::Rmm => 0b100,
+            RoundingMode::Dyn => 0b111,
+        }
+    }
+}
+
+/// An instruction without operands, encoded as a fixed word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemOp {
+    Ecall,
+    Ebreak,
+    Wfi,
+    Mret,
+    Sret,
+    /// The Zihintpause hint, a `fence` with only the `w` predecessor set.
+    Pause,
+}
+
+impl SystemOp {
+    pub fn word(self) -> u32 {
+        match self {
+            SystemOp::Ecall => 0x00000073,
+            SystemOp::Ebreak => 0x00100073,
+            SystemOp::Wfi => 0x10500073,
+            SystemOp::Mret => 0x30200073,
+            SystemOp::Sret => 0x10200073,
+            SystemOp::Pause => 0x0100000f,
+        }
+    }
+}
+
+/// A Zbb operation on a single register, encoded as an I-type word with a
+/// fixed immediate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Clz,
+    Ctz,
+    Cpop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UOp {
+    Lui,
+    Auipc,
+}
+
+/// A parsed instruction, grouped by encoding format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,
+    R {
+        op: ROp,
+        rd: u32,
+        rs1: u32,
+        rs2: u32,
+    },
+    I {
+        op: IOp,
+        rd: u32,
+        rs1: u32,
+        imm: Immediate,
+    },
+    Load {
+        op: LoadOp,
+        rd: u32,
+        rs1: u32,
+        imm: Immediate,
+    },
+    Store {
+        op: StoreOp,
+        rs1: u32,
+        rs2: u32,
+        imm: Immediate,
+    },
+    /// An R-type float operation; registers are floating-point registers.
+    FR {
+        op: FOp,
+        rd: u32,
+        rs1: u32,
+        rs2: u32,
+        rm: RoundingMode,
+    },
+    Unary {
+        op: UnaryOp,
+        rd: u32,
+        rs1: u32,
+    },
+    Branch {
+        op: BranchOp,
+        rs1: u32,
+        rs2: u32,
+        label: String,
+    },
+    U {
+        op: UOp,
+        rd: u32,
+        imm: Immediate,
+    },
+    Jal {
+        rd: u32,
+        label: String,
+    },
+    System {
+        op: SystemOp,
+    },
+    /// Order page-table updates for the virtual address in `rs1` and the
+    /// ASID in `rs2`, where `x0` stands for all of them.
+    SfenceVma {
+        rs1: u32,
+        rs2: u32,
+    },
+    /// An instruction encoded ahead of time, such as one from an ISA spec.
+    Raw {
+        format: &'static str,
+        word: u32,
+    },
+}
+
+impl Instruction {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Nop => "nop",
+            Instruction::R { op, .. } => match op {
+                ROp::And => "and",
+                ROp::Or => "or",
+                ROp::Add => "add",
+                ROp::Sub => "sub",
+                ROp::Slt => "slt",
+                ROp::Sltu => "sltu",
+                ROp::Mul => "mul",
+                ROp::Mulh => "mulh",
+                ROp::Mulhsu => "mulhsu",
+                ROp::Mulhu => "mulhu",
+                ROp::Div => "div",
+                ROp::Divu => "divu",
+                ROp::Rem => "rem",
+                ROp::Remu => "remu",
+                ROp::Andn => "andn",
+                ROp::Orn => "orn",
+                ROp::Xnor => "xnor",
+                ROp::Min => "min",
+                ROp::Minu => "minu",
+                ROp::Max => "max",
+                ROp::Maxu => "maxu",
+            },
+            Instruction::I { op, .. } => match op {
+                IOp::Addi => "addi",
+            },
+            Instruction::Load { op, .. } => match op {
+                LoadOp::Ld => "ld",
+                LoadOp::Lw => "lw",
+                LoadOp::Flw => "flw",
+            },
+            Instruction::Store { op, .. } => match op {
+                StoreOp::Sd => "sd",
+                StoreOp::Fsw => "fsw",
+            },
+            Instruction::FR { op, .. } => match op {
+                FOp::Fadd => "fadd.s",
+                FOp::Fsub => "fsub.s",
+                FOp::Fmul => "fmul.s",
+                FOp::Fdiv => "fdiv.s",
+            },
+            Instruction::Unary { op, .. } => match op {
+                UnaryOp::Clz => "clz",
+                UnaryOp::Ctz => "ctz",
+                UnaryOp::Cpop => "cpop",
+            },
+            Instruction::Branch { op, .. } => match op {
+                BranchOp::Beq => "beq",
+                BranchOp::Bne => "bne",
+                BranchOp::Blt => "blt",
+                BranchOp::Bge => "bge",
+                BranchOp::Bltu => "bltu",
+                BranchOp::Bgeu => "bgeu",
+            },
+            Instruction::U { op, .. } => match op {
+                UOp::Lui => "lui",
+                UOp::Auipc => "auipc",
+            },
+            Instruction::Jal { .. } => "jal",
+            Instruction::System { op } => match op {
+                SystemOp::Ecall => "ecall",
+                SystemOp::Ebreak => "ebreak",
+                SystemOp::Wfi => "wfi",
+                SystemOp::Mret => "mret",
+                SystemOp::Sret => "sret",
+                SystemOp::Pause => "pause",
+            },
+            Instruction::SfenceVma { .. } => "sfence.vma",
+            Instruction::Raw { .. } => ".insn",
+        }
+    }
+
+    /// The encoding format: `R`, `I`, `S`, `B`, `U` or `J`. The all-zero
+    /// `nop` counts as `I`, like the `addi x0, x0, 0` it stands for.
+    pub fn format(&self) -> &'static str {
+        match self {
+            Instruction::Nop
+            | Instruction::I { .. }
+            | Instruction::Load { .. }
+            | Instruction::Unary { .. }
+            | Instruction::System { .. } => "I",
+            Instruction::R { .. } | Instruction::FR { .. } | Instruction::SfenceVma { .. } => "R",
+            Instruction::Store { .. } => "S",
+            Instruction::Branch { .. } => "B",
+            Instruction::U { .. } => "U",
+            Instruction::Jal { .. } => "J",
+            Instruction::Raw { format, .. } => format,
+        }
+    }
+
+    /// The integer destination register, if the instruction writes one.
+    pub fn rd(&self) -> Option<u32> {
+        match self {
+            Instruction::R { rd, .. }
+            | Instruction::I { rd, .. }
+            | Instruction::Unary { rd, .. }
+            | Instruction::Load {
+                op: LoadOp::Ld | LoadOp::Lw,
+                rd,
+                ..
+            }
+            | Instruction::U { rd, .. }
+            | Instruction::Jal { rd, .. } => Some(*rd),
+            _ => None,
+        }
+    }
+
+    /// The label reference that must be resolved into this instruction.
+    pub fn relocation(&self) -> Option<Relocation> {
+        match self {
+            Instruction::Branch { label, .. } => Some(Relocation::Branch(label.clone())),
+            Instruction::Jal { label, .. } => Some(Relocation::Jump(label.clone())),
+            Instruction::I { imm, .. }
+            | Instruction::Load { imm, .. }
+            | Instruction::Store { imm, .. }
+            | Instruction::U { imm, .. } => match imm {
+                Immediate::Relocation(relocation) => Some(relocation.clone()),
+                Immediate::Value(_) => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Encode the instruction, leaving any relocated fields zero.
+    pub fn encode(&self) -> u32 {
+        let value = |imm: &Immediate| match imm {
+            Immediate::Value(imm) => *imm as u32,
+            Immediate::Relocation(_) => 0,
+        };
+        let mut instruction: u32 = 0;
+        match self {
+            Instruction::Nop => {}
+            Instruction::R { op, rd, rs1, rs2 } => {
+                let (funct3, funct7) = match op {
+                    ROp::And => (0b111, 0b0000000),
+                    ROp::Or => (0b110, 0b0000000),
+                    ROp::Add => (0b000, 0b0000000),
+                    ROp::Sub => (0b000, 0b0100000),
+                    ROp::Slt => (0b010, 0b0000000),
+                    ROp::Sltu => (0b011, 0b0000000),
+                    ROp::Mul => (0b000, 0b0000001),
+                    ROp::Mulh => (0b001, 0b0000001),
+                    ROp::Mulhsu => (0b010, 0b0000001),
+                    ROp::Mulhu => (0b011, 0b0000001),
+                    ROp::Div => (0b100, 0b0000001),
+                    ROp::Divu => (0b101, 0b0000001),
+                    ROp::Rem => (0b110, 0b0000001),
+                    ROp::Remu => (0b111, 0b0000001),
+                    ROp::Andn => (0b111, 0b0100000),
+                    ROp::Orn => (0b110, 0b0100000),
+                    ROp::Xnor => (0b100, 0b0100000),
+                    ROp::Min => (0b100, 0b0000101),
+                    ROp::Minu => (0b101, 0b0000101),
+                    ROp::Max => (0b110, 0b0000101),
+                    ROp::Maxu => (0b111, 0b0000101),
+                };
+                instruction |= 0b0110011;
+                instruction |= rd << 7;
+                instruction |= funct3 << 12;
+                instruction |= rs1 << 15;
+                instruction |= rs2 << 20;
+                instruction |= funct7 << 25;
+            }
+            Instruction::I { op, rd, rs1, imm } => {
+                let funct3 = match op {
+                    IOp::Addi => 0b000,
+                };
+                instruction = build_itype(0b0010011, funct3, *rd, *rs1, value(imm) as i32);
+            }
+            Instruction::Load { op, rd, rs1, imm } => {
+                let (opcode, funct3) = match op {
+                    LoadOp::Ld => (0b0000011, 0b011),
+                    LoadOp::Lw => (0b0000011, 0b010),
+                    LoadOp::Flw => (0b0000111, 0b010),
+                };
+                instruction = build_itype(opcode, funct3, *rd, *rs1, value(imm) as i32);
+            }
+            Instruction::Store { op, rs1, rs2, imm } => {
+                let (opcode, funct3) = match op {
+                    StoreOp::Sd => (0b0100011, 0b011),
+                    StoreOp::Fsw => (0b0100111, 0b010),
+                };
+                instruction = build_stype(opcode, funct3, *rs1, *rs2, value(imm) as i32);
+            }
+            Instruction::FR {
+                op,
+                rd,
+                rs1,
+                rs2,
+                rm,
+            } => {
+                let funct7 = match op {
+                    FOp::Fadd => 0b0000000,
+                    FOp::Fsub => 0b0000100,
+                    FOp::Fmul => 0b0001000,
+                    FOp::Fdiv => 0b0001100,
+                };
+                instruction |= 0b1010011;
+                instruction |= rd << 7;
+                instruction |= rm.funct3() << 12;
+                instruction |= rs1 << 15;
+                instruction |= rs2 << 20;
+                instruction |= funct7 << 25;
+            }
+            Instruction::Unary { op, rd, rs1 } => {
+                // funct7 `0b0110000`, with the operation in the rs2 field.
+                let rs2 = match op {
+                    UnaryOp::Clz => 0b00000,
+                    UnaryOp::Ctz => 0b00001,
+                    UnaryOp::Cpop => 0b00010,
+                };
+                instruction |= 0b0010011;
+                instruction |= rd << 7;
+                instruction |= 0b001 << 12;
+                instruction |= rs1 << 15;
+                instruction |= rs2 << 20;
+                instruction |= 0b0110000 << 25;
+            }
+            Instruction::Branch { op, rs1, rs2, .. } => {
+                let funct3 = match op {
+                    BranchOp::Beq => 0b000,
+                    BranchOp::Bne => 0b001,
+                    BranchOp::Blt => 0b100,
+                    BranchOp::Bge => 0b101,
+                    BranchOp::Bltu => 0b110,
+                    BranchOp::Bgeu => 0b111,
+                };
+                instruction |= 0b1100011;
+                instruction |= funct3 << 12;
+                instruction |= rs1 << 15;
+                instruction |= rs2 << 20;
+            }
+            Instruction::U { op, rd, imm } => {
+                let opcode = match op {
+                    UOp::Lui => 0b0110111,
+                    UOp::Auipc => 0b0010111,
+                };
+                instruction |= opcode;
+                instruction |= rd << 7;
+                instruction |= value(imm) << 12;
+            }
+            Instruction::Jal { rd, .. } => {
+                instruction |= 0b1101111;
+                instruction |= rd << 7;
+            }
+            Instruction::System { op } => instruction = op.word(),
+            Instruction::SfenceVma { rs1, rs2 } => {
+                instruction |= 0b1110011;
+                instruction |= rs1 << 15;
+                instruction |= rs2 << 20;
+                instruction |= 0b0001001 << 25;
+            }
+            Instruction::Raw { word, .. } => instruction = *word,
+        }
+        instruction
+    }
+}
+
+/// Encode an I-type instruction, keeping the low 12 bits of the signed
+/// immediate at bits 31:20.
+pub fn build_itype(opcode: u32, funct3: u32, rd: u32, rs1: u32, imm: i32) -> u32 {
+    let mut instruction = opcode;
+    instruction |= rd << 7;
+    instruction |= funct3 << 12;
+    instruction |= rs1 << 15;
+    instruction |= (imm as u32 & 0xfff) << 20;
+    instruction
+}
+
+/// Encode an S-type instruction, splitting the 12-bit signed immediate into
+/// imm[4:0] at bits 11:7 and imm[11:5] at bits 31:25.
+pub fn build_stype(opcode: u32, funct3: u32, rs1: u32, rs2: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    let mut instruction = opcode;
+    instruction |= (imm & 0b11111) << 7;
+    instruction |= funct3 << 12;
+    instruction |= rs1 << 15;
+    instruction |= rs2 << 20;
+    instruction |= ((imm >> 5) & 0b1111111) << 25;
+    instruction
+}
+
+#[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn itype() {
+        // Expected words from `llvm-mc -triple=riscv64 -show-encoding`.
+        assert_eq!(build_itype(0b0010011, 0b000, 5, 6, -1), 0xfff30293);
+        assert_eq!(build_itype(0b0010011, 0b000, 5, 6, -2048), 0x80030293);
+        assert_eq!(build_itype(0b0000011, 0b011, 5, 6, 2047), 0x7ff33283);
+    }
+
+    #[test]
+    fn stype() {
+        // The inline split `parse_sd` used before `build_stype`.
+        let inline = |rs1: u32, rs2: u32, imm: i32| {
+            let imm = imm as u32;
+            let mut instruction: u32 = 0;
+            instruction |= 0b0100011;
+            instruction |= (imm & 0b00000000_00000000_00000000_00011111) << 7;
+            instruction |= 0b011 << 12;
+            instruction |= rs1 << 15;
+            instruction |= rs2 << 20;
+            instruction |= (imm & 0b00000000_00000000_00001111_11100000) << 20;
+            instruction
+        };
+        for imm in [0, 1, 31, 32, 40, 2047, -1, -8, -2048] {
+            assert_eq!(build_stype(0b0100011, 0b011, 6, 5, imm), inline(6, 5, imm));
+        }
+        // Expected words from `llvm-mc -triple=riscv64 -show-encoding`.
+        assert_eq!(build_stype(0b0100011, 0b011, 6, 5, -8), 0xfe533c23);
+        assert_eq!(build_stype(0b0100011, 0b011, 6, 5, -2048), 0x80533023);
+    }
+}
@@ -0,0 +1,248 @@
+//! Run assembled programs on a tiny RV64 interpreter of the base integer
+//! subset, so tests can check what a program computes.
+//!
+//! ```
+//! use risc_v_assembler::interpret::check_execution;
+//!
+//! let asm = "addi x5, x0, 2\nadd x6, x5, x5\nsd x6, 8(x0)";
+//! assert_eq!(check_execution(asm, &[(6, 4)], &[(8, 4)]), Ok(()));
+//! ```
+
+use crate::assemble_program;
+use crate::disassemble::branch_target;
+use std::convert::TryInto;
+
+/// The bytes of memory `check_execution` gives a program.
+const MEMORY_SIZE: usize = 4096;
+
+/// The instructions `check_execution` runs before giving up on a program.
+const MAX_STEPS: usize = 100_000;
+
+/// The state of a hart. Instructions are fetched from the program rather
+/// than from `memory`, and `pc` counts instructions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Machine {
+    pub registers: [u64; 32],
+    pub memory: Vec<u8>,
+    pub pc: usize,
+}
+
+impl Machine {
+    /// A machine with every register zero and `memory` bytes of zeroed
+    /// memory.
+    pub fn new(memory: usize) -> Self {
+        Machine {
+            registers: [0; 32],
+            memory: vec![0; memory],
+            pc: 0,
+        }
+    }
+
+    /// Run `program` until it runs off its end or reaches `ecall` or
+    /// `ebreak`, for at most `steps` instructions.
+    pub fn run(&mut self, program: &[u32], steps: usize) -> Result<(), String> {
+        for _ in 0..steps {
+            match program.get(self.pc) {
+                Some(0x00000073 | 0x00100073) | None => return Ok(()),
+                Some(inst) => self.step(*inst)?,
+            }
+        }
+        Err(format!(
+            "Invalid Program: still running after {} steps",
+            steps
+        ))
+    }
+
+    /// Read `width` bytes at `address` as a little-endian number.
+    pub fn read(&self, address: usize, width: usize) -> Result<u64, String> {
+        let bytes = address
+            .checked_add(width)
+            .and_then(|end| self.memory.get(address..end))
+            .ok_or_else(|| format!("Invalid Address: `{:#x}`", address))?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, byte| value << 8 | *byte as u64))
+    }
+
+    fn write(&mut self, address: usize, width: usize, value: u64) -> Result<(), String> {
+        let bytes = address
+            .checked_add(width)
+            .and_then(|end| self.memory.get_mut(address..end))
+            .ok_or_else(|| format!("Invalid Address: `{:#x}`", address))?;
+        bytes.copy_from_slice(&value.to_le_bytes()[..width]);
+        Ok(())
+    }
+
+    fn step(&mut self, inst: u32) -> Result<(), String> {
+        let opcode = inst & 0b1111111;
+        let rd = ((inst >> 7) & 0b11111) as usize;
+        let funct3 = (inst >> 12) & 0b111;
+        let rs1 = self.registers[((inst >> 15) & 0b11111) as usize];
+        let rs2 = self.registers[((inst >> 20) & 0b11111) as usize];
+        let funct7 = inst >> 25;
+        let i_imm = (inst as i32 >> 20) as u64;
+        let s_imm = (((inst as i32 >> 25) << 5) | ((inst >> 7) & 0b11111) as i32) as u64;
+        let u_imm = (inst & 0xfffff000) as i32 as u64;
+        let address = |base: u64, offset: u64| base.wrapping_add(offset) as usize;
+        let invalid = || format!("Invalid Instruction: `{:#010x}`", inst);
+        let mut next = self.pc + 1;
+        let value = match (opcode, funct3, funct7) {
+            // The all-zero `nop`.
+            _ if inst == 0 => None,
+            (0b0110011, 0b000, 0) => Some(rs1.wrapping_add(rs2)),
+            (0b0110011, 0b000, 0b0100000) => Some(rs1.wrapping_sub(rs2)),
+            (0b0110011, 0b111, 0) => Some(rs1 & rs2),
+            (0b0110011, 0b110, 0) => Some(rs1 | rs2),
+            (0b0110011, 0b010, 0) => Some(((rs1 as i64) < rs2 as i64) as u64),
+            (0b0110011, 0b011, 0) => Some((rs1 < rs2) as u64),
+            (0b0010011, 0b000, _) => Some(rs1.wrapping_add(i_imm)),
+            (0b0110111, _, _) => Some(u_imm),
+            (0b0010111, _, _) => Some(((self.pc * 4) as u64).wrapping_add(u_imm)),
+            (0b0000011, 0b011, _) => Some(self.read(address(rs1, i_imm), 8)?),
+            (0b0000011, 0b010, _) => Some(self.read(address(rs1, i_imm), 4)? as u32 as i32 as u64),
+            (0b0100011, 0b011, _) => {
+                self.write(address(rs1, s_imm), 8, rs2)?;
+                None
+            }
+            (0b1100011, _, _) => {
+                let taken = match funct3 {
+                    0b000 => rs1 == rs2,
+                    0b001 => rs1 != rs2,
+                    0b100 => (rs1 as i64) < rs2 as i64,
+                    0b101 => rs1 as i64 >= rs2 as i64,
+                    0b110 => rs1 < rs2,
+                    0b111 => rs1 >= rs2,
+                    _ => return Err(invalid()),
+                };
+                if taken {
+                    next = self.target(inst)?;
+                }
+                None
+            }
+            (0b1101111, _, _) => {
+                next = self.target(inst)?;
+                Some(((self.pc + 1) * 4) as u64)
+            }
+            _ => return Err(invalid()),
+        };
+        if let (Some(value), 1..) = (value, rd) {
+            self.registers[rd] = value;
+        }
+        self.pc = next;
+        Ok(())
+    }
+
+    fn target(&self, inst: u32) -> Result<usize, String> {
+        branch_target(self.pc, inst)
+            .and_then(|target| target.try_into().ok())
+            .ok_or_else(|| format!("Invalid Branch: `{:#010x}` leaves the program", inst))
+    }
+}
+
+/// Assemble and run `asm`, then check that each listed register holds its
+/// value and each listed doubleword of memory, by byte address, holds its
+/// value. Data in `.data` is loaded at address 0. The error describes the
+/// first difference.
+pub fn check_execution(
+    asm: &str,
+    registers: &[(usize, u64)],
+    memory: &[(usize, u64)],
+) -> Result<(), String> {
+    let program = assemble_program(asm).map_err(|err| err.to_string())?;
+    let mut machine = Machine::new(MEMORY_SIZE);
+    for (i, word) in program.data.iter().enumerate() {
+        machine.write(i * 4, 4, *word as u64)?;
+    }
+    machine
+        .run(&program.instructions, MAX_STEPS)
+        .map_err(|err| format!("{} at {:#x}", err, machine.pc * 4))?;
+    for (register, expected) in registers {
+        let found = *machine
+            .registers
+            .get(*register)
+            .ok_or_else(|| format!("Invalid Register: `x{}`", register))?;
+        if found != *expected {
+            return Err(format!(
+                "Mismatch in `x{}`: expected `{:#x}`, found `{:#x}`",
+                register, expected, found
+            ));
+        }
+    }
+    for (address, expected) in memory {
+        let found = machine.read(*address, 8)?;
+        if found != *expected {
+            return Err(format!(
+                "Mismatch at `{:#x}`: expected `{:#x}`, found `{:#x}`",
+                address, expected, found
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn sum_loop() {
+        let asm = "addi x5, x0, 10\naddi x6, x0, 0\nLoop:\nadd x6, x6, x5\naddi x5, x5, -1\nbne x5, x0, Loop\nsd x6, 16(x0)";
+        assert_eq!(
+            check_execution(asm, &[(5, 0), (6, 55)], &[(16, 55)]),
+            Ok(())
+        );
+        assert_eq!(
+            check_execution(asm, &[(6, 54)], &[]),
+            Err("Mismatch in `x6`: expected `0x36`, found `0x37`".to_string())
+        );
+    }
+
+    #[test]
+    fn jumps_and_loads() {
+        let asm = "jal Skip\naddi x7, x0, 1\nSkip:\naddi x5, x0, -3\nadd x5, x5, x5\nsd x5, 8(x0)\nld x6, 8(x0)\nebreak\naddi x7, x0, 2";
+        let registers = [(1, 4), (6, -6i64 as u64), (7, 0)];
+        assert_eq!(
+            check_execution(asm, &registers, &[(8, -6i64 as u64)]),
+            Ok(())
+        );
+        let asm = ".data\n.word 0xfffffffe\n.text\nlw x5, 0(x0)\nslt x6, x5, x0\nsltu x7, x5, x0";
+        let registers = [(5, -2i64 as u64), (6, 1), (7, 0)];
+        assert_eq!(check_execution(asm, &registers, &[]), Ok(()));
+    }
+
+    #[test]
+    fn errors() {
+        let mut machine = Machine::new(16);
+        let program = assemble("Loop:\nj Loop").unwrap();
+        assert_eq!(
+            machine.run(&program, 10),
+            Err("Invalid Program: still running after 10 steps".to_string())
+        );
+        let mut machine = Machine::new(16);
+        let program = assemble("ld x5, 16(x0)").unwrap();
+        assert_eq!(
+            machine.run(&program, 10),
+            Err("Invalid Address: `0x10`".to_string())
+        );
+        let mut machine = Machine::new(16);
+        assert_eq!(
+            machine.run(&[0xffffffff], 10),
+            Err("Invalid Instruction: `0xffffffff`".to_string())
+        );
+        // The address wraps around to the top of the address space.
+        assert_eq!(
+            check_execution("ld x5, -8(x0)", &[], &[]),
+            Err("Invalid Address: `0xfffffffffffffff8` at 0x0".to_string())
+        );
+        assert_eq!(
+            check_execution("sd x5, -8(x0)", &[], &[]),
+            Err("Invalid Address: `0xfffffffffffffff8` at 0x0".to_string())
+        );
+        assert_eq!(
+            check_execution("nop", &[(32, 0)], &[]),
+            Err("Invalid Register: `x32`".to_string())
+        );
+    }
+}
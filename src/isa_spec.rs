@@ -0,0 +1,197 @@
+//! Custom instruction encodings loaded from an ISA spec file.
+//!
+//! A spec is a small subset of TOML: one `[[instruction]]` table per
+//! instruction, with `key = value` pairs and `#` comments.
+//!
+//! ```text
+//! [[instruction]]
+//! mnemonic = "foo"
+//! format = "R"        # R: `foo rd, rs1, rs2`, I: `foo rd, rs1, imm`
+//! opcode = 0b0001011
+//! funct3 = 0
+//! funct7 = 0          # R-type only
+//! ```
+
+use crate::instruction::Instruction;
+use crate::{parse_immediate, register, AssembleError, COM, REG, SEP, SIMM};
+use regex::Regex;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CustomFormat {
+    R,
+    I,
+}
+
+/// An instruction defined by an ISA spec.
+#[derive(Debug, Clone)]
+pub struct CustomInstruction {
+    pub mnemonic: String,
+    pub format: CustomFormat,
+    pub opcode: u32,
+    pub funct3: u32,
+    pub funct7: u32,
+    regex: Regex,
+}
+
+impl CustomInstruction {
+    fn new(
+        mnemonic: String,
+        format: CustomFormat,
+        opcode: u32,
+        funct3: u32,
+        funct7: u32,
+    ) -> Result<Self, AssembleError> {
+        let operand = match format {
+            CustomFormat::R => *REG,
+            CustomFormat::I => SIMM.as_str(),
+        };
+        let regex = format!(
+            r"^\s*{m}\s+{r}{s}{r}{s}{o}{c}$",
+            m = regex::escape(&mnemonic),
+            r = *REG,
+            s = *SEP,
+            o = operand,
+            c = *COM
+        );
+        let regex = Regex::new(&regex)
+            .map_err(|_| AssembleError::InvalidSpec(format!("`mnemonic = {}`", mnemonic)))?;
+        Ok(CustomInstruction {
+            mnemonic,
+            format,
+            opcode,
+            funct3,
+            funct7,
+            regex,
+        })
+    }
+
+    /// Parse `line` as this instruction, encoding it into a raw word.
+    pub(crate) fn parse(&self, line: &str) -> Result<Option<Instruction>, AssembleError> {
+        if let Some(caps) = self.regex.captures(line) {
+            let rd = register(&caps[1])?;
+            let rs1 = register(&caps[2])?;
+            let mut word = self.opcode | rd << 7 | self.funct3 << 12 | rs1 << 15;
+            match self.format {
+                CustomFormat::R => word |= register(&caps[3])? << 20 | self.funct7 << 25,
+                CustomFormat::I => {
+                    let imm = parse_immediate(&caps[3])?;
+                    if !(-2048..=2047).contains(&imm) {
+                        return Err(AssembleError::InvalidImmediate(caps[3].to_string()));
+                    }
+                    word |= (imm as u32) << 20;
+                }
+            }
+            let format = match self.format {
+                CustomFormat::R => "R",
+                CustomFormat::I => "I",
+            };
+            Ok(Some(Instruction::Raw { format, word }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Parse an ISA spec into its instructions.
+pub fn parse_spec(spec: &str) -> Result<Vec<CustomInstruction>, AssembleError> {
+    let mut tables: Vec<HashMap<String, String>> = Vec::new();
+    for (n, line) in spec.lines().enumerate() {
+        let err =
+            |message: &str| AssembleError::InvalidSpec(format!("line {}: {}", n + 1, message));
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        } else if line == "[[instruction]]" {
+            tables.push(HashMap::new());
+        } else if let Some((key, value)) = line.split_once('=') {
+            let table = tables
+                .last_mut()
+                .ok_or_else(|| err("expected `[[instruction]]`"))?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            table.insert(key.trim().to_string(), value.to_string());
+        } else {
+            return Err(err(line));
+        }
+    }
+    tables.iter().map(custom_instruction).collect()
+}
+
+fn custom_instruction(table: &HashMap<String, String>) -> Result<CustomInstruction, AssembleError> {
+    let get = |key: &str| {
+        table
+            .get(key)
+            .ok_or_else(|| AssembleError::InvalidSpec(format!("missing `{}`", key)))
+    };
+    let field = |key: &str, bits: u32| -> Result<u32, AssembleError> {
+        let value = match table.get(key) {
+            Some(value) => value,
+            None => return Ok(0),
+        };
+        let err = || AssembleError::InvalidSpec(format!("`{} = {}`", key, value));
+        let parsed = match value.strip_prefix("0b") {
+            Some(binary) => i64::from_str_radix(binary, 2).map_err(|_| err())?,
+            None => parse_immediate(value).map_err(|_| err())?,
+        };
+        if !(0..1 << bits).contains(&parsed) {
+            return Err(err());
+        }
+        Ok(parsed as u32)
+    };
+    let mnemonic = get("mnemonic")?.to_string();
+    let format = match get("format")?.as_str() {
+        "R" => CustomFormat::R,
+        "I" => CustomFormat::I,
+        format => return Err(AssembleError::InvalidSpec(format!("`format = {}`", format))),
+    };
+    get("opcode")?;
+    CustomInstruction::new(
+        mnemonic,
+        format,
+        field("opcode", 7)?,
+        field("funct3", 3)?,
+        field("funct7", 7)?,
+    )
+}
+
+#[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)]
+mod tests {
+    use super::*;
+    use crate::{assemble_program_with, Options};
+
+    #[test]
+    fn custom_r_type() {
+        let spec = "# A fake R-type instruction\n\
+                    [[instruction]]\n\
+                    mnemonic = \"foo\"\n\
+                    format = \"R\"\n\
+                    opcode = 0b0001011\n\
+                    funct3 = 0b010\n\
+                    funct7 = 0x01\n";
+        let options = Options {
+            isa: parse_spec(spec).unwrap(),
+            ..Options::default()
+        };
+        let program = assemble_program_with("foo x5, x6, x7\nnop", &options).unwrap();
+        assert_eq!(
+            program.instructions,
+            [0b0000001_00111_00110_010_00101_0001011, 0]
+        );
+        assert!(assemble_program_with("foo x5, x6, x7", &Options::default()).is_err());
+    }
+
+    #[test]
+    fn invalid_spec() {
+        assert!(parse_spec("mnemonic = \"foo\"").is_err());
+        assert!(parse_spec("[[instruction]]\nmnemonic = \"foo\"\nformat = \"R\"").is_err());
+        assert!(
+            parse_spec("[[instruction]]\nmnemonic = \"foo\"\nformat = \"R\"\nopcode = 0x80")
+                .is_err()
+        );
+    }
+}
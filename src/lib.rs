@@ -0,0 +1,4807 @@
+#[macro_use]
+extern crate lazy_static;
+
+pub mod builder;
+pub mod disassemble;
+pub mod instruction;
+pub mod interpret;
+pub mod isa_spec;
+pub mod output;
+
+use disassemble::{RegNames, ABI_NAMES, FP_ABI_NAMES};
+use instruction::{
+    build_itype, build_stype, BranchOp, FOp, IOp, Immediate, Instruction, LoadOp, ROp, Relocation,
+    RoundingMode, StoreOp, SystemOp, UOp, UnaryOp,
+};
+use isa_spec::{CustomFormat, CustomInstruction};
+use output::OutputFormat;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+lazy_static! {
+    // Operand patterns match no surrounding whitespace, so that each run of
+    // whitespace between tokens is consumed by exactly one `\s*`.
+    pub(crate) static ref REG: &'static str = r"([a-z]\w*)";
+    pub(crate) static ref SEP: &'static str = r"\s*,\s*";
+    // Numbers may use `_` as a digit separator, as in `1_000` or `0x1000_0000`.
+    static ref DEC: &'static str = r"\d+(?:_\d+)*";
+    static ref HEX: &'static str = r"0[xX][0-9a-fA-F]+(?:_[0-9a-fA-F]+)*";
+    // Memory offsets may be negative, as in `ld x5, -8(x2)`.
+    static ref NUM: String = format!(r"(-?{d})", d=*DEC);
+    static ref IMM: String = format!(r"({h}|{d})", h=*HEX, d=*DEC);
+    // A character literal such as `'A'` or `'\n'` stands for its code.
+    static ref CHR: &'static str = r"'(?:[^'\\]|\\.)*'";
+    pub(crate) static ref SIMM: String = format!(r"(-?(?:{h}|{d})|{ch})", h=*HEX, d=*DEC, ch=*CHR);
+    static ref LAB: &'static str = r"(\w+)";
+    pub(crate) static ref COM: &'static str = r"\s*(//.*)?";
+    static ref NOP_STR: String = format!(r"^\s*nop{c}$", c=*COM);
+    static ref NOP_REGEX: Regex = Regex::new(&NOP_STR).unwrap(); // nop
+    static ref SYSTEM_STR: String = format!(r"^\s*(ecall|ebreak|wfi|mret|sret|pause){c}$", c=*COM);
+    static ref SYSTEM_REGEX: Regex = Regex::new(&SYSTEM_STR).unwrap(); // ecall | wfi
+    static ref SFENCE_STR: String = format!(r"^\s*sfence\.vma(?:\s+{r}(?:{s}{r})?)?{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref SFENCE_REGEX: Regex = Regex::new(&SFENCE_STR).unwrap(); // sfence.vma | sfence.vma x5, x6
+    static ref LOAD_STR: String = format!(r"^\s*(ld|lw)\s+{r}{s}(?:{n}|%lo\(\s*{l}\s*\))\s*\(\s*{r}\s*\){c}$", r=*REG, s=*SEP, n=*NUM, l=*LAB, c=*COM);
+    static ref LOAD_REGEX: Regex = Regex::new(&LOAD_STR).unwrap(); // ld x5, 40(x6) | lw x5, %lo(Data)(x6)
+    static ref LOAD_SYMBOL_STR: String = format!(r"^\s*(ld|lw)\s+{r}{s}([A-Za-z_]\w*){c}$", r=*REG, s=*SEP, c=*COM);
+    static ref LOAD_SYMBOL_REGEX: Regex = Regex::new(&LOAD_SYMBOL_STR).unwrap(); // lw x5, Data
+    static ref SD_STR: String = format!(r"^\s*sd\s+{r}{s}(?:{n}|%lo\(\s*{l}\s*\))\s*\(\s*{r}\s*\){c}$", r=*REG, s=*SEP, n=*NUM, l=*LAB, c=*COM);
+    static ref SD_REGEX: Regex = Regex::new(&SD_STR).unwrap(); // sd x5, 40(x6) | sd x5, %lo(Data)(x6)
+    static ref AND_STR: String = format!(r"^\s*and\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref AND_REGEX: Regex = Regex::new(&AND_STR).unwrap(); // and x5, x6, x7
+    static ref OR_STR: String = format!(r"^\s*or\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref OR_REGEX: Regex = Regex::new(&OR_STR).unwrap(); // or x5, x6, x7
+    static ref ADD_STR: String = format!(r"^\s*add\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref ADD_REGEX: Regex = Regex::new(&ADD_STR).unwrap(); // add x5, x6, x7
+    static ref SUB_STR: String = format!(r"^\s*sub\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref SUB_REGEX: Regex = Regex::new(&SUB_STR).unwrap(); // sub x5, x6, x7
+    static ref SLT_STR: String = format!(r"^\s*slt\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref SLT_REGEX: Regex = Regex::new(&SLT_STR).unwrap(); // slt x5, x6, x7
+    static ref SLTU_STR: String = format!(r"^\s*sltu\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref SLTU_REGEX: Regex = Regex::new(&SLTU_STR).unwrap(); // sltu x5, x6, x7
+    static ref SLTZ_STR: String = format!(r"^\s*sltz\s+{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref SLTZ_REGEX: Regex = Regex::new(&SLTZ_STR).unwrap(); // sltz x5, x6
+    static ref SGTZ_STR: String = format!(r"^\s*sgtz\s+{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref SGTZ_REGEX: Regex = Regex::new(&SGTZ_STR).unwrap(); // sgtz x5, x6
+    static ref SGT_STR: String = format!(r"^\s*sgt\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref SGT_REGEX: Regex = Regex::new(&SGT_STR).unwrap(); // sgt x5, x6, x7
+    static ref SGTU_STR: String = format!(r"^\s*sgtu\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref SGTU_REGEX: Regex = Regex::new(&SGTU_STR).unwrap(); // sgtu x5, x6, x7
+    static ref M_STR: String = format!(r"^\s*(mul|mulh|mulhsu|mulhu|div|divu|rem|remu)\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref M_REGEX: Regex = Regex::new(&M_STR).unwrap(); // mul x5, x6, x7 | div x5, x6, x7
+    static ref BEQ_STR: String = format!(r"^\s*beq\s+{r}{s}{r}{s}{l}{c}$", r=*REG, s=*SEP, l=*LAB, c=*COM);
+    static ref BEQ_REGEX: Regex = Regex::new(&BEQ_STR).unwrap(); // beq x5, x6, Label
+    static ref BLT_STR: String = format!(r"^\s*blt\s+{r}{s}{r}{s}{l}{c}$", r=*REG, s=*SEP, l=*LAB, c=*COM);
+    static ref BLT_REGEX: Regex = Regex::new(&BLT_STR).unwrap(); // blt x5, x6, Label
+    static ref BRANCH_STR: String = format!(r"^\s*(bne|bge|bltu|bgeu)\s+{r}{s}{r}{s}{l}{c}$", r=*REG, s=*SEP, l=*LAB, c=*COM);
+    static ref BRANCH_REGEX: Regex = Regex::new(&BRANCH_STR).unwrap(); // bne x5, x6, Label | bgeu x5, x6, Label
+    static ref SWAPPED_STR: String = format!(r"^\s*(bgt|ble|bgtu|bleu)\s+{r}{s}{r}{s}{l}{c}$", r=*REG, s=*SEP, l=*LAB, c=*COM);
+    static ref SWAPPED_REGEX: Regex = Regex::new(&SWAPPED_STR).unwrap(); // bgt x5, x6, Label | bleu x5, x6, Label
+    static ref ADDI_STR: String = format!(r"^\s*addi\s+{r}{s}{r}{s}(?:{i}|%pcrel_lo\(\s*{l}\s*\)|%lo\(\s*{l}\s*\)){c}$", r=*REG, s=*SEP, i=*SIMM, l=*LAB, c=*COM);
+    static ref ADDI_REGEX: Regex = Regex::new(&ADDI_STR).unwrap(); // addi x5, x6, -1 | addi x5, x5, %pcrel_lo(1b) | addi x5, x5, %lo(Label)
+    static ref AUIPC_STR: String = format!(r"^\s*auipc\s+{r}{s}(?:{i}|%pcrel_hi\(\s*{l}\s*\)){c}$", r=*REG, s=*SEP, i=*IMM, l=*LAB, c=*COM);
+    static ref AUIPC_REGEX: Regex = Regex::new(&AUIPC_STR).unwrap(); // auipc x5, 0x12345 | auipc x5, %pcrel_hi(Label)
+    static ref LUI_STR: String = format!(r"^\s*lui\s+{r}{s}(?:{i}|%hi\(\s*{l}\s*\)){c}$", r=*REG, s=*SEP, i=*IMM, l=*LAB, c=*COM);
+    static ref LUI_REGEX: Regex = Regex::new(&LUI_STR).unwrap(); // lui x5, 0x12345 | lui x5, %hi(Label)
+    static ref LI_STR: String = format!(r"^\s*li\s+{r}{s}{i}{c}$", r=*REG, s=*SEP, i=*SIMM, c=*COM);
+    static ref LI_REGEX: Regex = Regex::new(&LI_STR).unwrap(); // li x5, 0x12345678
+}
+
+// Jumps and label addresses.
+lazy_static! {
+    static ref J_STR: String = format!(r"^\s*j\s+{l}{c}$", l=*LAB, c=*COM);
+    static ref J_REGEX: Regex = Regex::new(&J_STR).unwrap(); // j Label
+    static ref JAL_STR: String = format!(r"^\s*jal\s+(?:{r}{s})?{l}{c}$", r=*REG, s=*SEP, l=*LAB, c=*COM);
+    static ref JAL_REGEX: Regex = Regex::new(&JAL_STR).unwrap(); // jal x1, Label | jal Label
+    static ref CALL_STR: String = format!(r"^\s*(call|tail)\s+{l}(?:@plt)?{c}$", l=*LAB, c=*COM);
+    static ref CALL_REGEX: Regex = Regex::new(&CALL_STR).unwrap(); // call Label | tail printf@plt
+    static ref LA_STR: String = format!(r"^\s*(la|lla)\s+{r}{s}([A-Za-z_]\w*){c}$", r=*REG, s=*SEP, c=*COM);
+    static ref LA_REGEX: Regex = Regex::new(&LA_STR).unwrap(); // la x5, Data | lla x5, Data
+}
+
+// F extension.
+lazy_static! {
+    static ref FLW_STR: String = format!(r"^\s*flw\s+{r}{s}{n}\s*\(\s*{r}\s*\){c}$", r=*REG, s=*SEP, n=*NUM, c=*COM);
+    static ref FLW_REGEX: Regex = Regex::new(&FLW_STR).unwrap(); // flw f1, 40(x6)
+    static ref FSW_STR: String = format!(r"^\s*fsw\s+{r}{s}{n}\s*\(\s*{r}\s*\){c}$", r=*REG, s=*SEP, n=*NUM, c=*COM);
+    static ref FSW_REGEX: Regex = Regex::new(&FSW_STR).unwrap(); // fsw f1, 40(x6)
+    static ref RM: &'static str = r"(rne|rtz|rdn|rup|rmm|dyn)";
+    static ref FR_STR: String = format!(r"^\s*(fadd|fsub|fmul|fdiv)\.s\s+{r}{s}{r}{s}{r}(?:{s}{m})?{c}$", r=*REG, s=*SEP, m=*RM, c=*COM);
+    static ref FR_REGEX: Regex = Regex::new(&FR_STR).unwrap(); // fadd.s f1, f2, f3 | fadd.s f1, f2, f3, rtz
+}
+
+// Zbb extension.
+lazy_static! {
+    static ref ZBB_STR: String = format!(r"^\s*(andn|orn|xnor|min|minu|max|maxu)\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref ZBB_REGEX: Regex = Regex::new(&ZBB_STR).unwrap(); // andn x5, x6, x7 | min x5, x6, x7
+    static ref ZBB_UNARY_STR: String = format!(r"^\s*(clz|ctz|cpop)\s+{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
+    static ref ZBB_UNARY_REGEX: Regex = Regex::new(&ZBB_UNARY_STR).unwrap(); // clz x5, x6
+}
+
+// Labels and directives.
+lazy_static! {
+    static ref LABEL_STR: String = format!(r"^\s*{l}\s*:{c}$", l=*LAB, c=*COM);
+    static ref LABEL_REGEX: Regex = Regex::new(&LABEL_STR).unwrap(); // Label:
+    static ref ISA_COMMENT_REGEX: Regex = Regex::new(r"^\s*//\s*risc-v:\s*(\S+)\s*$").unwrap(); // // risc-v: rv64im
+    static ref LABEL_PREFIX_STR: String = format!(r"^\s*{l}\s*:\s*(\S.*)$", l=*LAB);
+    static ref LABEL_PREFIX_REGEX: Regex = Regex::new(&LABEL_PREFIX_STR).unwrap(); // Data: .word 0x10
+    // The expression may nest one level of parentheses.
+    static ref OFFSET_EXPR_STR: String = format!(r"^(\s*(?:ld|lw|sd|flw|fsw)\s+{r}{s})\(((?:[^()]|\([^()]*\))*)\)(\s*\(\s*{r}\s*\).*)$", r=*REG, s=*SEP);
+    static ref OFFSET_EXPR_REGEX: Regex = Regex::new(&OFFSET_EXPR_STR).unwrap(); // ld x5, (SIZE*8)(x6)
+    static ref ORG_STR: String = format!(r"^\s*\.org\s+{i}{c}$", i=*IMM, c=*COM);
+    static ref ORG_REGEX: Regex = Regex::new(&ORG_STR).unwrap(); // .org 0x1000
+    static ref SKIP_STR: String = format!(r"^\s*\.(?:skip|space)\s+{i}{c}$", i=*IMM, c=*COM);
+    static ref SKIP_REGEX: Regex = Regex::new(&SKIP_STR).unwrap(); // .skip 16 | .space 16
+    static ref EQU_STR: String = format!(r"^\s*\.equ\s+{l}{s}{i}{c}$", l=*LAB, s=*SEP, i=*SIMM, c=*COM);
+    static ref EQU_REGEX: Regex = Regex::new(&EQU_STR).unwrap(); // .equ DEBUG, 1
+    static ref IFDEF_STR: String = format!(r"^\s*\.if(n)?def\s+{l}{c}$", l=*LAB, c=*COM);
+    static ref IFDEF_REGEX: Regex = Regex::new(&IFDEF_STR).unwrap(); // .ifdef DEBUG | .ifndef DEBUG
+    static ref ELSE_STR: String = format!(r"^\s*\.else{c}$", c=*COM);
+    static ref ELSE_REGEX: Regex = Regex::new(&ELSE_STR).unwrap(); // .else
+    static ref ENDIF_STR: String = format!(r"^\s*\.endif{c}$", c=*COM);
+    static ref ENDIF_REGEX: Regex = Regex::new(&ENDIF_STR).unwrap(); // .endif
+    static ref MACRO_STR: String = format!(r"^\s*\.macro\s+{l}((?:\s*,?\s*\w+)*){c}$", l=*LAB, c=*COM);
+    static ref MACRO_REGEX: Regex = Regex::new(&MACRO_STR).unwrap(); // .macro push reg
+    static ref ENDM_STR: String = format!(r"^\s*\.endm{c}$", c=*COM);
+    static ref ENDM_REGEX: Regex = Regex::new(&ENDM_STR).unwrap(); // .endm
+    static ref REPT_STR: String = format!(r"^\s*\.rept\s+{i}{c}$", i=*IMM, c=*COM);
+    static ref REPT_REGEX: Regex = Regex::new(&REPT_STR).unwrap(); // .rept 4
+    static ref ENDR_STR: String = format!(r"^\s*\.endr{c}$", c=*COM);
+    static ref ENDR_REGEX: Regex = Regex::new(&ENDR_STR).unwrap(); // .endr
+    static ref ERROR_STR: String = format!(r#"^\s*\.error\s+"([^"]*)"{c}$"#, c=*COM);
+    static ref ERROR_REGEX: Regex = Regex::new(&ERROR_STR).unwrap(); // .error "message"
+    static ref GLOBL_STR: String = format!(r"^\s*\.glob(?:a)?l\s+{l}{c}$", l=*LAB, c=*COM);
+    static ref GLOBL_REGEX: Regex = Regex::new(&GLOBL_STR).unwrap(); // .globl Label
+    static ref TYPE_STR: String = format!(r"^\s*\.type\s+{l}{s}[@%](function|object|notype){c}$", l=*LAB, s=*SEP, c=*COM);
+    static ref TYPE_REGEX: Regex = Regex::new(&TYPE_STR).unwrap(); // .type main, @function
+    static ref SIZE_STR: String = format!(r"^\s*\.size\s+{l}{s}(?:\.\s*-\s*{l}|{i}){c}$", l=*LAB, s=*SEP, i=*IMM, c=*COM);
+    static ref SIZE_REGEX: Regex = Regex::new(&SIZE_STR).unwrap(); // .size main, .-main | .size table, 16
+    // `//` inside the string is consumed by the string, not the comment.
+    static ref ASCII_STR: String = format!(r#"^\s*\.(ascii|asciz)\s+"((?:[^"\\]|\\.)*)"{c}$"#, c=*COM);
+    static ref ASCII_REGEX: Regex = Regex::new(&ASCII_STR).unwrap(); // .ascii "text" | .asciz "text\n"
+    static ref INSN_STR: String = format!(r"^\s*\.insn\s+([risbuj])\s+(.*?){c}$", c=*COM);
+    static ref INSN_REGEX: Regex = Regex::new(&INSN_STR).unwrap(); // .insn r 0x33, 0, 0, x5, x6, x7
+    static ref BYTE_STR: String = format!(r"^\s*\.byte\s+({i}(?:{s}{i})*){c}$", i=*SIMM, s=*SEP, c=*COM);
+    static ref BYTE_REGEX: Regex = Regex::new(&BYTE_STR).unwrap(); // .byte 0x7f, 'E', 76
+    static ref WORD_STR: String = format!(r"^\s*\.(word|2byte|4byte|8byte)\s+({i}(?:{s}{i})*){c}$", i=*SIMM, s=*SEP, c=*COM);
+    static ref WORD_REGEX: Regex = Regex::new(&WORD_STR).unwrap(); // .word 0x10, -1 | .2byte 7 | .8byte 0x0123456789abcdef
+    static ref SIMM_REGEX: Regex = Regex::new(&SIMM).unwrap(); // -1 | 0x7f | 'E'
+    static ref SECTION_STR: String = format!(r"^\s*\.(text|data){c}$", c=*COM);
+    static ref SECTION_REGEX: Regex = Regex::new(&SECTION_STR).unwrap(); // .text | .data
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssembleError {
+    InvalidInstruction(String),
+    UnknownDirective(String),
+    /// A known directive with malformed operands.
+    InvalidDirective(String),
+    InvalidImmediate(String),
+    InvalidString(String),
+    InvalidLabel {
+        label: String,
+        suggestion: Option<String>,
+    },
+    UnresolvedLabel(String),
+    ReservedLabel(String),
+    DuplicateLabel(String),
+    BackwardOrigin {
+        current: usize,
+        requested: usize,
+    },
+    UnalignedOrigin(usize),
+    UnalignedSkip(usize),
+    /// `.org` or `.skip` padding of more than `MAX_PADDING` bytes.
+    PaddingTooLarge(usize),
+    InvalidRelocation(String),
+    UnbalancedParentheses(String),
+    InvalidRegister(String),
+    /// A floating-point register used where an integer register is expected.
+    FpRegister(String),
+    UnbalancedConditional(String),
+    InvalidSpec(String),
+    InvalidMacro(String),
+    ErrorDirective(String),
+    /// An instruction outside the base ISA of `--profile`.
+    OutsideProfile(String),
+    BranchOutOfRange {
+        label: String,
+        distance: isize,
+    },
+    /// A program of `needed` words for a buffer of `capacity` words.
+    BufferTooSmall {
+        needed: usize,
+        capacity: usize,
+    },
+}
+
+/// The radix used for offsets in diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetFormat {
+    Decimal,
+    Hex,
+}
+
+impl FromStr for OffsetFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "decimal" => Ok(OffsetFormat::Decimal),
+            "hex" => Ok(OffsetFormat::Hex),
+            _ => Err(format!(
+                "Invalid Offset Format: `{}` (expected `decimal` or `hex`)",
+                s
+            )),
+        }
+    }
+}
+
+impl AssembleError {
+    /// Display the error with offsets in the given radix.
+    pub fn display(&self, offsets: OffsetFormat) -> ErrorDisplay<'_> {
+        ErrorDisplay {
+            error: self,
+            offsets,
+        }
+    }
+}
+
+/// An `AssembleError` displayed with a chosen `OffsetFormat`.
+pub struct ErrorDisplay<'a> {
+    error: &'a AssembleError,
+    offsets: OffsetFormat,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.display(OffsetFormat::Decimal).fmt(f)
+    }
+}
+
+impl fmt::Display for ErrorDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.error {
+            AssembleError::InvalidInstruction(line) => write!(f, "Invalid Instruction: `{}`", line),
+            AssembleError::UnknownDirective(directive) => {
+                write!(f, "Unknown Directive: `{}`", directive)
+            }
+            AssembleError::InvalidImmediate(imm) => write!(f, "Invalid Immediate: `{}`", imm),
+            AssembleError::InvalidString(string) => write!(f, "Invalid String: `\"{}\"`", string),
+            AssembleError::InvalidLabel { label, suggestion } => {
+                write!(f, "Invalid Label: `{}`", label)?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, " (did you mean `{}`?)", suggestion)?;
+                }
+                Ok(())
+            }
+            AssembleError::UnresolvedLabel(label) => write!(f, "Unresolved Label: `{}`", label),
+            AssembleError::DuplicateLabel(label) => {
+                write!(f, "Invalid Label: `{}` is already defined", label)
+            }
+            AssembleError::ReservedLabel(label) => write!(
+                f,
+                "Invalid Label: `{}` is the name of a register or mnemonic",
+                label
+            ),
+            AssembleError::BackwardOrigin { current, requested } => write!(
+                f,
+                "Invalid Origin: `.org` cannot move backwards (current offset {:#x}, requested offset {:#x})",
+                current, requested
+            ),
+            AssembleError::UnalignedOrigin(requested) => write!(
+                f,
+                "Invalid Origin: `.org` offset {:#x} is not word-aligned",
+                requested
+            ),
+            AssembleError::UnalignedSkip(count) => write!(
+                f,
+                "Invalid Skip: `.skip` count {} is not a multiple of 4",
+                count
+            ),
+            AssembleError::PaddingTooLarge(bytes) => write!(
+                f,
+                "Invalid Padding: {:#x} bytes is more than the limit of {:#x}",
+                bytes, MAX_PADDING
+            ),
+            AssembleError::InvalidRelocation(label) => write!(
+                f,
+                "Invalid Relocation: `%pcrel_lo({})` does not refer to an `auipc` with `%pcrel_hi`",
+                label
+            ),
+            AssembleError::UnbalancedParentheses(mnemonic) => write!(
+                f,
+                "{}: unbalanced parentheses in address operand",
+                mnemonic
+            ),
+            AssembleError::InvalidRegister(name) => write!(f, "Invalid Register: `{}`", name),
+            AssembleError::FpRegister(name) => write!(
+                f,
+                "Invalid Register: `{}` is a floating-point register",
+                name
+            ),
+            AssembleError::UnbalancedConditional(directive) => {
+                write!(f, "Invalid Conditional: unmatched `{}`", directive)
+            }
+            AssembleError::InvalidSpec(message) => write!(f, "Invalid ISA Spec: {}", message),
+            AssembleError::InvalidMacro(message) => write!(f, "Invalid Macro: {}", message),
+            AssembleError::InvalidDirective(message) => {
+                write!(f, "Invalid Directive: {}", message)
+            }
+            AssembleError::ErrorDirective(message) => write!(f, "{}", message),
+            AssembleError::OutsideProfile(mnemonic) => {
+                write!(f, "Invalid Instruction: `{}` is outside the profile", mnemonic)
+            }
+            AssembleError::BranchOutOfRange { label, distance } => {
+                let distance = match self.offsets {
+                    OffsetFormat::Decimal => distance.to_string(),
+                    OffsetFormat::Hex if *distance < 0 => format!("-{:#x}", -distance),
+                    OffsetFormat::Hex => format!("{:#x}", distance),
+                };
+                write!(
+                    f,
+                    "Invalid Branch: `{}` is {} instructions away",
+                    label, distance
+                )
+            }
+            AssembleError::BufferTooSmall { needed, capacity } => write!(
+                f,
+                "Invalid Buffer: the program needs {} words, but the buffer holds {}",
+                needed, capacity
+            ),
+        }
+    }
+}
+
+impl Error for AssembleError {}
+
+/// Label definitions. Numeric labels such as `1:` are local and may be
+/// redefined; they are referenced as `1b` (backward) or `1f` (forward).
+#[derive(Debug, Default)]
+struct Labels {
+    global: HashMap<String, Position>,
+    local: HashMap<String, Vec<Position>>,
+}
+
+/// Where a label is defined: the index of the next instruction laid out,
+/// and the byte address it is placed at. Seeded labels lie outside the
+/// program, so their index is `usize::MAX`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Position {
+    index: usize,
+    address: usize,
+}
+
+impl Labels {
+    fn insert(&mut self, label: String, position: Position) {
+        if label.chars().all(|c| c.is_ascii_digit()) {
+            self.local.entry(label).or_default().push(position);
+        } else {
+            self.global.insert(label, position);
+        }
+    }
+
+    /// Resolve `label` as referenced by the instruction at index `i`.
+    fn resolve(&self, label: &str, i: usize) -> Option<Position> {
+        let local = |name: &str| self.local.get(name).map(Vec::as_slice).unwrap_or(&[]);
+        if let Some(name) = label.strip_suffix('b') {
+            local(name).iter().rev().find(|j| j.index <= i).copied()
+        } else if let Some(name) = label.strip_suffix('f') {
+            local(name).iter().find(|j| j.index > i).copied()
+        } else {
+            None
+        }
+        .or_else(|| self.global.get(label).copied())
+    }
+}
+
+/// An assembled program together with its symbol table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    /// The words of the `.text` section.
+    pub instructions: Vec<u32>,
+    /// The words of the `.data` section.
+    pub data: Vec<u32>,
+    pub symbols: BTreeMap<String, Symbol>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    /// The byte address of the symbol.
+    pub address: usize,
+    /// Whether the symbol was exported with `.globl`/`.global`.
+    pub global: bool,
+    /// The symbol type declared with `.type`, if any.
+    pub kind: Option<SymbolType>,
+    /// The size in bytes declared with `.size`, if any.
+    pub size: Option<usize>,
+}
+
+/// Mnemonics taking an `offset(base)` address operand.
+const MEMORY_MNEMONICS: &[&str] = &["ld", "lw", "sd", "flw", "fsw"];
+
+/// Every directive, including those handled before a line is parsed.
+const DIRECTIVES: &[&str] = &[
+    ".org",
+    ".skip",
+    ".space",
+    ".globl",
+    ".global",
+    ".text",
+    ".data",
+    ".equ",
+    ".macro",
+    ".endm",
+    ".rept",
+    ".endr",
+    ".ifdef",
+    ".ifndef",
+    ".else",
+    ".endif",
+    ".error",
+    ".include",
+    ".include_once",
+    ".ascii",
+    ".asciz",
+    ".byte",
+    ".word",
+    ".2byte",
+    ".4byte",
+    ".8byte",
+    ".insn",
+    ".type",
+    ".size",
+];
+
+/// The part of the ISA an instruction belongs to. A pseudo-instruction
+/// belongs to the part its expansion does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Subset {
+    Rv32i,
+    /// The instructions RV64I adds to RV32I.
+    Rv64i,
+    M,
+    F,
+    Privileged,
+    Zbb,
+}
+
+/// Every instruction and pseudo-instruction mnemonic, with its operands and
+/// the part of the ISA it belongs to.
+const MNEMONICS: &[(&str, &str, Subset)] = &[
+    ("nop", "", Subset::Rv32i),
+    ("ld", "rd, offset(rs1)", Subset::Rv64i),
+    ("lw", "rd, offset(rs1)", Subset::Rv32i),
+    ("sd", "rs2, offset(rs1)", Subset::Rv64i),
+    ("and", "rd, rs1, rs2", Subset::Rv32i),
+    ("or", "rd, rs1, rs2", Subset::Rv32i),
+    ("add", "rd, rs1, rs2", Subset::Rv32i),
+    ("sub", "rd, rs1, rs2", Subset::Rv32i),
+    ("slt", "rd, rs1, rs2", Subset::Rv32i),
+    ("sltu", "rd, rs1, rs2", Subset::Rv32i),
+    ("sltz", "rd, rs1", Subset::Rv32i),
+    ("sgtz", "rd, rs1", Subset::Rv32i),
+    ("sgt", "rd, rs1, rs2", Subset::Rv32i),
+    ("sgtu", "rd, rs1, rs2", Subset::Rv32i),
+    ("mul", "rd, rs1, rs2", Subset::M),
+    ("mulh", "rd, rs1, rs2", Subset::M),
+    ("mulhsu", "rd, rs1, rs2", Subset::M),
+    ("mulhu", "rd, rs1, rs2", Subset::M),
+    ("div", "rd, rs1, rs2", Subset::M),
+    ("divu", "rd, rs1, rs2", Subset::M),
+    ("rem", "rd, rs1, rs2", Subset::M),
+    ("remu", "rd, rs1, rs2", Subset::M),
+    ("beq", "rs1, rs2, label", Subset::Rv32i),
+    ("bne", "rs1, rs2, label", Subset::Rv32i),
+    ("blt", "rs1, rs2, label", Subset::Rv32i),
+    ("bge", "rs1, rs2, label", Subset::Rv32i),
+    ("bltu", "rs1, rs2, label", Subset::Rv32i),
+    ("bgeu", "rs1, rs2, label", Subset::Rv32i),
+    ("bgt", "rs1, rs2, label", Subset::Rv32i),
+    ("ble", "rs1, rs2, label", Subset::Rv32i),
+    ("bgtu", "rs1, rs2, label", Subset::Rv32i),
+    ("bleu", "rs1, rs2, label", Subset::Rv32i),
+    ("addi", "rd, rs1, imm", Subset::Rv32i),
+    ("auipc", "rd, imm", Subset::Rv32i),
+    ("lui", "rd, imm", Subset::Rv32i),
+    ("li", "rd, imm", Subset::Rv32i),
+    ("la", "rd, symbol", Subset::Rv32i),
+    ("lla", "rd, symbol", Subset::Rv32i),
+    ("j", "label", Subset::Rv32i),
+    ("jal", "[rd, ]label", Subset::Rv32i),
+    ("call", "label", Subset::Rv32i),
+    ("tail", "label", Subset::Rv32i),
+    ("ecall", "", Subset::Rv32i),
+    ("ebreak", "", Subset::Rv32i),
+    ("wfi", "", Subset::Privileged),
+    ("mret", "", Subset::Privileged),
+    ("sret", "", Subset::Privileged),
+    ("sfence.vma", "[rs1[, rs2]]", Subset::Privileged),
+    ("pause", "", Subset::Rv32i),
+    ("flw", "rd, offset(rs1)", Subset::F),
+    ("fsw", "rs2, offset(rs1)", Subset::F),
+    ("fadd.s", "rd, rs1, rs2[, rm]", Subset::F),
+    ("fsub.s", "rd, rs1, rs2[, rm]", Subset::F),
+    ("fmul.s", "rd, rs1, rs2[, rm]", Subset::F),
+    ("fdiv.s", "rd, rs1, rs2[, rm]", Subset::F),
+];
+
+/// The Zbb mnemonics, accepted with `--ext zbb`, with their operands.
+const ZBB_MNEMONICS: &[(&str, &str, Subset)] = &[
+    ("andn", "rd, rs1, rs2", Subset::Zbb),
+    ("orn", "rd, rs1, rs2", Subset::Zbb),
+    ("xnor", "rd, rs1, rs2", Subset::Zbb),
+    ("min", "rd, rs1, rs2", Subset::Zbb),
+    ("minu", "rd, rs1, rs2", Subset::Zbb),
+    ("max", "rd, rs1, rs2", Subset::Zbb),
+    ("maxu", "rd, rs1, rs2", Subset::Zbb),
+    ("clz", "rd, rs1", Subset::Zbb),
+    ("ctz", "rd, rs1", Subset::Zbb),
+    ("cpop", "rd, rs1", Subset::Zbb),
+];
+
+fn is_mnemonic(name: &str) -> bool {
+    MNEMONICS.iter().any(|(mnemonic, ..)| *mnemonic == name)
+}
+
+/// Check that `statement` only uses instructions the profile allows.
+fn check_profile(options: &Options, statement: &Statement) -> Result<(), AssembleError> {
+    let insts = match (options.profile, statement) {
+        (Some(_), Statement::Instruction(inst)) => std::slice::from_ref(inst),
+        (Some(_), Statement::Pseudo(insts)) => insts.as_slice(),
+        _ => return Ok(()),
+    };
+    for inst in insts {
+        let mnemonic = inst.mnemonic();
+        let subset = MNEMONICS
+            .iter()
+            .chain(ZBB_MNEMONICS)
+            .find(|(name, ..)| *name == mnemonic)
+            .map(|(.., subset)| *subset);
+        // Instructions from an ISA spec or an enabled extension were asked
+        // for explicitly.
+        let allowed = |subset| match subset {
+            Subset::Rv32i => true,
+            Subset::Zbb => options.extensions.contains(&Extension::Zbb),
+            _ => false,
+        };
+        if subset.is_some_and(|subset| !allowed(subset)) {
+            return Err(AssembleError::OutsideProfile(mnemonic.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Every instruction accepted with `options`, written as its mnemonic and
+/// operands, such as `add rd, rs1, rs2`.
+pub fn instruction_syntax(options: &Options) -> Vec<String> {
+    let mut syntax: Vec<(&str, &str)> = MNEMONICS
+        .iter()
+        .chain(ZBB_MNEMONICS)
+        .filter(|(.., subset)| match subset {
+            Subset::Zbb => options.extensions.contains(&Extension::Zbb),
+            _ if options.profile == Some(Profile::Rv32i) => *subset == Subset::Rv32i,
+            _ => true,
+        })
+        .map(|(mnemonic, operands, _)| (*mnemonic, *operands))
+        .collect();
+    for custom in &options.isa {
+        let operands = match custom.format {
+            CustomFormat::R => "rd, rs1, rs2",
+            CustomFormat::I => "rd, rs1, imm",
+        };
+        syntax.push((&custom.mnemonic, operands));
+    }
+    syntax
+        .into_iter()
+        .map(|(mnemonic, operands)| format!("{} {}", mnemonic, operands).trim_end().to_string())
+        .collect()
+}
+
+/// A parsed source line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    Instruction(Instruction),
+    /// A pseudo-instruction and the instructions it expands to.
+    Pseudo(Vec<Instruction>),
+    Label(String),
+    Org(usize),
+    /// Reserve the given number of zero bytes.
+    Skip(usize),
+    /// Bytes from `.ascii`/`.asciz`/`.byte` and the sized-data directives.
+    /// Consecutive data packs into words, and the last word is zero-padded
+    /// when anything else follows.
+    Bytes(Vec<u8>),
+    Global(String),
+    /// The ELF symbol type of a label, from `.type`. It is kept as metadata and
+    /// emits no code.
+    Type(String, SymbolType),
+    /// The ELF symbol size of a label, from `.size`. It is kept as metadata and
+    /// emits no code.
+    Size(String, SymbolSize),
+    /// Switch the section following statements are assembled into.
+    Section(Section),
+    /// A directive this assembler does not know, skipped with a warning.
+    UnknownDirective(String),
+}
+
+/// The section a word is assembled into. Each section has its own base
+/// address, and `.org` offsets are relative to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Text,
+    Data,
+}
+
+/// A symbol type `.type` may declare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolType {
+    Function,
+    Object,
+    NoType,
+}
+
+/// A symbol size `.size` may declare.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolSize {
+    Bytes(usize),
+    /// `.-label`, the distance from the label to the directive.
+    Since(String),
+}
+
+/// An instruction line parsed by `parse_instruction`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Parsed {
+    Instruction(Instruction),
+    /// A pseudo-instruction and the instructions it expands to.
+    Pseudo(Vec<Instruction>),
+}
+
+impl From<Parsed> for Statement {
+    fn from(parsed: Parsed) -> Self {
+        match parsed {
+            Parsed::Instruction(inst) => Statement::Instruction(inst),
+            Parsed::Pseudo(insts) => Statement::Pseudo(insts),
+        }
+    }
+}
+
+type Matcher = fn(&str) -> Result<Option<Parsed>, AssembleError>;
+
+/// The instruction matchers, with the mnemonics each accepts. A line is
+/// only tried against the matchers for its mnemonic, in order.
+const MATCHERS: &[(&[&str], Matcher)] = &[
+    (&["nop"], |line| {
+        Ok(NOP_REGEX
+            .is_match(line)
+            .then_some(Parsed::Instruction(Instruction::Nop)))
+    }),
+    (
+        &["ecall", "ebreak", "wfi", "mret", "sret", "pause"],
+        |line| Ok(parse_system(line).map(Parsed::Instruction)),
+    ),
+    (&["sfence.vma"], |line| {
+        Ok(parse_sfence_vma(line)?.map(Parsed::Instruction))
+    }),
+    (&["ld", "lw"], |line| {
+        Ok(parse_load(line)?.map(Parsed::Instruction))
+    }),
+    (&["ld", "lw"], |line| {
+        Ok(parse_load_symbol(line)?.map(Parsed::Pseudo))
+    }),
+    (&["sd"], |line| Ok(parse_sd(line)?.map(Parsed::Instruction))),
+    (
+        &["flw", "fsw", "fadd.s", "fsub.s", "fmul.s", "fdiv.s"],
+        |line| Ok(parse_f(line)?.map(Parsed::Instruction)),
+    ),
+    (&["and"], |line| {
+        Ok(parse_and(line)?.map(Parsed::Instruction))
+    }),
+    (&["or"], |line| Ok(parse_or(line)?.map(Parsed::Instruction))),
+    (&["add"], |line| {
+        Ok(parse_add(line)?.map(Parsed::Instruction))
+    }),
+    (&["sub"], |line| {
+        Ok(parse_sub(line)?.map(Parsed::Instruction))
+    }),
+    (&["slt"], |line| {
+        Ok(parse_slt(line)?.map(Parsed::Instruction))
+    }),
+    (&["sltu"], |line| {
+        Ok(parse_sltu(line)?.map(Parsed::Instruction))
+    }),
+    (&["sltz", "sgtz", "sgt", "sgtu"], |line| {
+        Ok(parse_comparison(line)?.map(Parsed::Pseudo))
+    }),
+    (
+        &[
+            "mul", "mulh", "mulhsu", "mulhu", "div", "divu", "rem", "remu",
+        ],
+        |line| Ok(parse_m(line)?.map(Parsed::Instruction)),
+    ),
+    (&["beq"], |line| {
+        Ok(parse_beq(line)?.map(Parsed::Instruction))
+    }),
+    (&["blt"], |line| {
+        Ok(parse_blt(line)?.map(Parsed::Instruction))
+    }),
+    (&["bne", "bge", "bltu", "bgeu"], |line| {
+        Ok(parse_conditional_branch(line)?.map(Parsed::Instruction))
+    }),
+    (&["bgt", "ble", "bgtu", "bleu"], |line| {
+        Ok(parse_swapped_branch(line)?.map(Parsed::Instruction))
+    }),
+    (&["addi"], |line| {
+        Ok(parse_addi(line)?.map(Parsed::Instruction))
+    }),
+    (&["auipc"], |line| {
+        Ok(parse_auipc(line)?.map(Parsed::Instruction))
+    }),
+    (&["lui"], |line| {
+        Ok(parse_lui(line)?.map(Parsed::Instruction))
+    }),
+    (&["li"], |line| Ok(parse_li(line)?.map(Parsed::Pseudo))),
+    (&["la", "lla"], |line| {
+        Ok(parse_la(line)?.map(Parsed::Pseudo))
+    }),
+    (&["jal"], |line| {
+        Ok(parse_jal(line)?.map(Parsed::Instruction))
+    }),
+    (&["j", "call", "tail"], |line| {
+        Ok(parse_jump(line).map(Parsed::Pseudo))
+    }),
+];
+
+lazy_static! {
+    /// The matchers for each mnemonic, so a line is tried against only the
+    /// regexes that could match it.
+    static ref DISPATCH: HashMap<&'static str, Vec<Matcher>> = {
+        let mut dispatch: HashMap<&str, Vec<Matcher>> = HashMap::new();
+        for (mnemonics, matcher) in MATCHERS {
+            for mnemonic in *mnemonics {
+                dispatch.entry(mnemonic).or_default().push(*matcher);
+            }
+        }
+        dispatch
+    };
+}
+
+/// Parse one instruction line. Returns `None` if the line does not start
+/// with a known mnemonic, so it may be a label or directive, and an error if
+/// it does but its operands are malformed.
+pub fn parse_instruction(line: &str) -> Result<Option<Parsed>, AssembleError> {
+    let line = line.trim();
+    let code = strip_comment(line);
+    let mnemonic = code.split_whitespace().next().unwrap_or_default();
+    for matcher in DISPATCH
+        .get(mnemonic)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+    {
+        if let Some(parsed) = matcher(line)? {
+            return Ok(Some(parsed));
+        }
+    }
+    if MEMORY_MNEMONICS.contains(&mnemonic)
+        && code.matches('(').count() != code.matches(')').count()
+    {
+        return Err(AssembleError::UnbalancedParentheses(mnemonic.to_string()));
+    }
+    if is_mnemonic(mnemonic) {
+        return Err(AssembleError::InvalidInstruction(line.to_string()));
+    }
+    Ok(None)
+}
+
+fn parse_system(line: &str) -> Option<Instruction> {
+    let caps = SYSTEM_REGEX.captures(line)?;
+    let op = match &caps[1] {
+        "ecall" => SystemOp::Ecall,
+        "ebreak" => SystemOp::Ebreak,
+        "wfi" => SystemOp::Wfi,
+        "mret" => SystemOp::Mret,
+        "sret" => SystemOp::Sret,
+        _ => SystemOp::Pause,
+    };
+    Some(Instruction::System { op })
+}
+
+/// Parse `sfence.vma` with no operands, `rs1`, or `rs1, rs2`; an omitted
+/// register is `x0`.
+fn parse_sfence_vma(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = SFENCE_REGEX.captures(line) {
+        let operand = |i: usize| caps.get(i).map_or(Ok(0), |reg| register(reg.as_str()));
+        Ok(Some(Instruction::SfenceVma {
+            rs1: operand(1)?,
+            rs2: operand(2)?,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parse `jal rd, label`, or `jal label`, which links through `x1` as
+/// in GNU as.
+fn parse_jal(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = JAL_REGEX.captures(line) {
+        let rd = match caps.get(1) {
+            Some(rd) => register(rd.as_str())?,
+            None => 1,
+        };
+        let label = caps[2].to_string();
+        Ok(Some(Instruction::Jal { rd, label }))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parse `j`, `call` or `tail`, which all become a `jal`.
+fn parse_jump(line: &str) -> Option<Vec<Instruction>> {
+    if let Some(caps) = J_REGEX.captures(line) {
+        let label = caps[1].to_string();
+        Some(vec![Instruction::Jal { rd: 0, label }])
+    } else if let Some(caps) = CALL_REGEX.captures(line) {
+        // Without a linker a `@plt` suffix names an ordinary label, and a
+        // `jal` reaches any address in the program.
+        let rd = if &caps[1] == "call" { 1 } else { 0 };
+        let label = caps[2].to_string();
+        Some(vec![Instruction::Jal { rd, label }])
+    } else {
+        None
+    }
+}
+
+fn parse_line(line: &str) -> Result<Option<Statement>, AssembleError> {
+    let line = line.trim();
+    let statement = if line.is_empty() || line.starts_with("//") {
+        return Ok(None);
+    } else if let Some(parsed) = parse_instruction(line)? {
+        parsed.into()
+    } else if let Some(caps) = LABEL_REGEX.captures(line) {
+        let label = &caps[1];
+        if is_mnemonic(label)
+            || resolve_register(label).is_some()
+            || resolve_fp_register(label).is_some()
+        {
+            return Err(AssembleError::ReservedLabel(label.to_string()));
+        }
+        Statement::Label(label.to_string())
+    } else if let Some(caps) = ORG_REGEX.captures(line) {
+        let requested = parse_immediate(&caps[1])?;
+        let requested = requested
+            .try_into()
+            .map_err(|_| AssembleError::InvalidImmediate(caps[1].to_string()))?;
+        Statement::Org(requested)
+    } else if let Some(caps) = SKIP_REGEX.captures(line) {
+        let count = parse_immediate(&caps[1])?;
+        let count = count
+            .try_into()
+            .map_err(|_| AssembleError::InvalidImmediate(caps[1].to_string()))?;
+        Statement::Skip(count)
+    } else if let Some(caps) = GLOBL_REGEX.captures(line) {
+        Statement::Global(caps[1].to_string())
+    } else if let Some(caps) = TYPE_REGEX.captures(line) {
+        let kind = match &caps[2] {
+            "function" => SymbolType::Function,
+            "object" => SymbolType::Object,
+            _ => SymbolType::NoType,
+        };
+        Statement::Type(caps[1].to_string(), kind)
+    } else if let Some(caps) = SIZE_REGEX.captures(line) {
+        let size = match caps.get(2) {
+            Some(label) => SymbolSize::Since(label.as_str().to_string()),
+            None => {
+                let bytes = parse_immediate(&caps[3])?;
+                let bytes = bytes
+                    .try_into()
+                    .map_err(|_| AssembleError::InvalidImmediate(caps[3].to_string()))?;
+                SymbolSize::Bytes(bytes)
+            }
+        };
+        Statement::Size(caps[1].to_string(), size)
+    } else if let Some(caps) = SECTION_REGEX.captures(line) {
+        Statement::Section(if &caps[1] == "text" {
+            Section::Text
+        } else {
+            Section::Data
+        })
+    } else if let Some(caps) = ASCII_REGEX.captures(line) {
+        let string =
+            unescape(&caps[2]).ok_or_else(|| AssembleError::InvalidString(caps[2].to_string()))?;
+        let mut bytes = string.into_iter().collect::<String>().into_bytes();
+        if &caps[1] == "asciz" {
+            bytes.push(0);
+        }
+        Statement::Bytes(bytes)
+    } else if let Some(caps) = BYTE_REGEX.captures(line) {
+        // Split on the values themselves, since `','` contains a comma.
+        let bytes = SIMM_REGEX
+            .find_iter(&caps[1])
+            .map(|byte| {
+                let byte = byte.as_str();
+                match parse_immediate(byte)? {
+                    value @ -128..=255 => Ok(value as u8),
+                    _ => Err(AssembleError::InvalidImmediate(byte.to_string())),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Statement::Bytes(bytes)
+    } else if let Some(caps) = INSN_REGEX.captures(line) {
+        Statement::Instruction(parse_insn(line, &caps[1], &caps[2])?)
+    } else if let Some(caps) = WORD_REGEX.captures(line) {
+        // Each value is little-endian and may be signed or unsigned.
+        let size = match &caps[1] {
+            "2byte" => 2,
+            "8byte" => 8,
+            _ => 4,
+        };
+        let mut bytes = Vec::new();
+        for value in SIMM_REGEX.find_iter(&caps[2]) {
+            let value = value.as_str();
+            let parsed = parse_wide(value)?;
+            let bits = 8 * size as u32;
+            if !(-(1 << (bits - 1))..1 << bits).contains(&parsed) {
+                return Err(AssembleError::InvalidImmediate(value.to_string()));
+            }
+            bytes.extend(&parsed.to_le_bytes()[..size]);
+        }
+        Statement::Bytes(bytes)
+    } else {
+        let mnemonic = strip_comment(line)
+            .split_whitespace()
+            .next()
+            .unwrap_or_default();
+        if mnemonic.starts_with('.') && !DIRECTIVES.contains(&mnemonic) {
+            return Err(AssembleError::UnknownDirective(mnemonic.to_string()));
+        }
+        let expected = match mnemonic {
+            ".type" => Some("`.type <symbol>, @function`, `@object` or `@notype`"),
+            ".size" => Some("`.size <symbol>, <bytes>` or `.size <symbol>, .-<label>`"),
+            _ => None,
+        };
+        if let Some(expected) = expected {
+            return Err(AssembleError::InvalidDirective(format!(
+                "`{}`, expected {}",
+                line, expected
+            )));
+        }
+        return Err(AssembleError::InvalidInstruction(line.to_string()));
+    };
+    Ok(Some(statement))
+}
+
+/// Parse a whole program into statements, skipping blank and comment lines.
+pub fn parse(asm: &str) -> Result<Vec<Statement>, AssembleError> {
+    parse_with(asm, &Options::default())
+}
+
+/// Parse a whole program with the given options, skipping lines in
+/// conditional blocks that are not taken. Besides the predefined symbols,
+/// symbols may be defined in the source with `.equ`.
+pub fn parse_with(asm: &str, options: &Options) -> Result<Vec<Statement>, AssembleError> {
+    let statements = parse_numbered(asm.lines(), options).map_err(|err| err.error)?;
+    Ok(statements
+        .into_iter()
+        .map(|(_, statement)| statement)
+        .collect())
+}
+
+/// Parse the lines of a whole program, pairing each statement with where it
+/// starts in the source. Lines expanded from a macro are located at its
+/// invocation.
+fn parse_numbered<'a>(
+    asm: impl IntoIterator<Item = &'a str>,
+    options: &Options,
+) -> Result<Vec<(Span, Statement)>, LocatedError> {
+    let mut conditionals = Conditionals::new(options.defines.clone());
+    let mut macros = HashMap::new();
+    let mut definition: Option<(Span, String, Macro)> = None;
+    // The open `.rept` block: where it starts, its count, how many `.rept`
+    // blocks nested in it are open, and its body so far.
+    let mut repetition: Option<(Span, usize, usize, Vec<String>)> = None;
+    let mut invocations = 0;
+    // Lines still to parse, with their location and macro expansion depth.
+    let mut pending: VecDeque<(Span, usize, String)> = asm
+        .into_iter()
+        .enumerate()
+        .map(|(line, text)| {
+            let col = text.len() - text.trim_start().len();
+            (Span { line, col }, 0, text.to_string())
+        })
+        .collect();
+    let mut statements = Vec::new();
+    while let Some((span, depth, line)) = pending.pop_front() {
+        let line = line.as_str();
+        let at = |error| LocatedError {
+            span: Some(span),
+            error,
+        };
+        if let Some((_, name, body)) = definition.as_mut() {
+            if ENDM_REGEX.is_match(line) {
+                let name = std::mem::take(name);
+                let body = std::mem::take(body);
+                macros.insert(name, body);
+                definition = None;
+            } else {
+                body.body.push(line.to_string());
+            }
+            continue;
+        }
+        if let Some((start, count, nested, body)) = repetition.as_mut() {
+            if ENDR_REGEX.is_match(line) && *nested == 0 {
+                let (start, count, body) = (*start, *count, std::mem::take(body));
+                repetition = None;
+                if depth == MAX_MACRO_DEPTH {
+                    return Err(at(AssembleError::InvalidMacro(format!(
+                        "expansion deeper than {} levels",
+                        MAX_MACRO_DEPTH
+                    ))));
+                }
+                // As in a macro, `\@` is unique to each repetition, so the
+                // body may define labels.
+                let mut expansion = Vec::new();
+                for _ in 0..count {
+                    invocations += 1;
+                    let unique = invocations.to_string();
+                    expansion.extend(body.iter().map(|line| line.replace("\\@", &unique)));
+                }
+                for line in expansion.into_iter().rev() {
+                    pending.push_front((start, depth + 1, line));
+                }
+            } else {
+                if REPT_REGEX.is_match(line) {
+                    *nested += 1;
+                } else if ENDR_REGEX.is_match(line) {
+                    *nested -= 1;
+                }
+                body.push(line.to_string());
+            }
+            continue;
+        }
+        if !conditionals.assembles(line).map_err(at)? {
+            continue;
+        }
+        if let Some(caps) = LABEL_PREFIX_REGEX.captures(line) {
+            // A label may share its line with an instruction or directive,
+            // which then starts at the label's address.
+            pending.push_front((span, depth, caps[2].to_string()));
+            pending.push_front((span, depth, format!("{}:", &caps[1])));
+        } else if let Some(caps) = MACRO_REGEX.captures(line) {
+            let params = caps[2]
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|param| !param.is_empty())
+                .map(str::to_string)
+                .collect();
+            let body = Vec::new();
+            definition = Some((span, caps[1].to_string(), Macro { params, body }));
+        } else if let Some(caps) = REPT_REGEX.captures(line) {
+            let count = parse_immediate(&caps[1]).map_err(at)?;
+            let count = count
+                .try_into()
+                .map_err(|_| at(AssembleError::InvalidImmediate(caps[1].to_string())))?;
+            repetition = Some((span, count, 0, Vec::new()));
+        } else if ENDR_REGEX.is_match(line) {
+            return Err(at(AssembleError::InvalidMacro(
+                "`.endr` without `.rept`".to_string(),
+            )));
+        } else if let Some(caps) = ERROR_REGEX.captures(line) {
+            return Err(at(AssembleError::ErrorDirective(caps[1].to_string())));
+        } else if let Some(expansion) = expand_macro(&macros, line, &mut invocations).map_err(at)? {
+            if depth == MAX_MACRO_DEPTH {
+                return Err(at(AssembleError::InvalidMacro(format!(
+                    "expansion deeper than {} levels",
+                    MAX_MACRO_DEPTH
+                ))));
+            }
+            for line in expansion.into_iter().rev() {
+                pending.push_front((span, depth + 1, line));
+            }
+        } else if let Some(caps) = OFFSET_EXPR_REGEX.captures(line) {
+            // A constant offset is folded before the line is parsed, so its
+            // symbols are those defined so far.
+            let offset = evaluate(&caps[3], &conditionals.defines).map_err(at)?;
+            if !(-2048..=2047).contains(&offset) {
+                return Err(at(AssembleError::InvalidImmediate(caps[3].to_string())));
+            }
+            pending.push_front((span, depth, format!("{}{}{}", &caps[1], offset, &caps[4])));
+        } else if let Some(inst) = parse_custom(&options.isa, line).map_err(at)? {
+            statements.push((span, Statement::Instruction(inst)));
+        } else if let Some(inst) = parse_extensions(&options.extensions, line).map_err(at)? {
+            let statement = Statement::Instruction(inst);
+            check_profile(options, &statement).map_err(at)?;
+            statements.push((span, statement));
+        } else {
+            match parse_line(line) {
+                Ok(Some(statement)) => {
+                    check_profile(options, &statement).map_err(at)?;
+                    statements.push((span, statement))
+                }
+                Ok(None) => {}
+                Err(AssembleError::UnknownDirective(directive))
+                    if options.ignore_unknown_directives =>
+                {
+                    statements.push((span, Statement::UnknownDirective(directive)))
+                }
+                Err(error) => return Err(at(error)),
+            }
+        }
+    }
+    if let Some((span, name, _)) = definition {
+        return Err(LocatedError {
+            span: Some(span),
+            error: AssembleError::InvalidMacro(format!("`{}` is missing `.endm`", name)),
+        });
+    }
+    if let Some((span, ..)) = repetition {
+        return Err(LocatedError {
+            span: Some(span),
+            error: AssembleError::InvalidMacro("`.rept` is missing `.endr`".to_string()),
+        });
+    }
+    if !conditionals.is_closed() {
+        return Err(LocatedError {
+            span: None,
+            error: AssembleError::UnbalancedConditional(".ifdef".to_string()),
+        });
+    }
+    Ok(statements)
+}
+
+/// The open `.ifdef`/`.ifndef` blocks and the symbols they test, followed
+/// line by line through a program.
+#[derive(Debug, Clone, Default)]
+pub struct Conditionals {
+    /// For each open block, whether the enclosing block is taken and
+    /// whether this block is taken.
+    blocks: Vec<(bool, bool)>,
+    defines: HashMap<String, i64>,
+}
+
+impl Conditionals {
+    /// Conditionals outside any block, with `defines` predefined as by
+    /// `-D`.
+    pub fn new(defines: HashMap<String, i64>) -> Self {
+        Conditionals {
+            blocks: Vec::new(),
+            defines,
+        }
+    }
+
+    /// Follow `line`, returning whether it is left to be assembled.
+    /// Conditional directives and `.equ` are handled here, and lines in
+    /// blocks not taken are skipped.
+    pub fn assembles(&mut self, line: &str) -> Result<bool, AssembleError> {
+        let unmatched =
+            |directive: &str| AssembleError::UnbalancedConditional(directive.to_string());
+        let active = self.blocks.last().is_none_or(|(_, taken)| *taken);
+        if let Some(caps) = IFDEF_REGEX.captures(line) {
+            let defined = self.defines.contains_key(&caps[2]);
+            self.blocks
+                .push((active, active && defined != caps.get(1).is_some()));
+        } else if ELSE_REGEX.is_match(line) {
+            let (enclosing, taken) = self.blocks.pop().ok_or_else(|| unmatched(".else"))?;
+            self.blocks.push((enclosing, enclosing && !taken));
+        } else if ENDIF_REGEX.is_match(line) {
+            self.blocks.pop().ok_or_else(|| unmatched(".endif"))?;
+        } else if !active {
+        } else if let Some(caps) = EQU_REGEX.captures(line) {
+            self.defines
+                .insert(caps[1].to_string(), parse_immediate(&caps[2])?);
+        } else {
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Whether every block has been closed with `.endif`.
+    pub fn is_closed(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+/// The deepest macro invocations may nest, so that a macro invoking itself
+/// fails instead of expanding forever.
+const MAX_MACRO_DEPTH: usize = 64;
+
+/// A macro defined with `.macro name params` ... `.endm`.
+#[derive(Debug, Default)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+/// Expand `line` if it invokes one of `macros`, substituting `\param` with
+/// its argument and `\@` with a number unique to the invocation.
+fn expand_macro(
+    macros: &HashMap<String, Macro>,
+    line: &str,
+    invocations: &mut usize,
+) -> Result<Option<Vec<String>>, AssembleError> {
+    let code = strip_comment(line).trim();
+    let (name, args) = match code.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (code, ""),
+    };
+    let definition = match macros.get(name) {
+        Some(definition) => definition,
+        None => return Ok(None),
+    };
+    let args: Vec<&str> = if args.is_empty() {
+        Vec::new()
+    } else {
+        args.split(',').map(str::trim).collect()
+    };
+    if args.len() != definition.params.len() {
+        return Err(AssembleError::InvalidMacro(format!(
+            "`{}` expects {} argument(s), got {}",
+            name,
+            definition.params.len(),
+            args.len()
+        )));
+    }
+    *invocations += 1;
+    // Substitute longer parameters first, so `\ab` is not taken for `\a`.
+    let mut params: Vec<(&String, &str)> = definition.params.iter().zip(args).collect();
+    params.sort_by_key(|(param, _)| std::cmp::Reverse(param.len()));
+    let expansion = definition
+        .body
+        .iter()
+        .map(|line| {
+            let mut line = line.replace("\\@", &invocations.to_string());
+            for (param, arg) in &params {
+                line = line.replace(&format!("\\{}", param), arg);
+            }
+            line
+        })
+        .collect();
+    Ok(Some(expansion))
+}
+
+/// Assemble a whole program, resolving branch labels.
+pub fn assemble(asm: &str) -> Result<Vec<u32>, AssembleError> {
+    assemble_from_lines(&asm.lines().collect::<Vec<_>>())
+}
+
+/// Assemble a program already split into lines, such as the lines of a
+/// notebook cell.
+pub fn assemble_from_lines(lines: &[&str]) -> Result<Vec<u32>, AssembleError> {
+    let program = assemble_lines(lines.iter().copied(), &Options::default());
+    Ok(program.map_err(|err| err.error)?.instructions)
+}
+
+/// Assemble a program into `out`, returning the number of words written.
+/// The words are encoded straight into `out` rather than collected first,
+/// and nothing is written unless the whole program fits and assembles.
+pub fn assemble_into(asm: &str, out: &mut [u32]) -> Result<usize, AssembleError> {
+    let options = Options::default();
+    let numbered = parse_numbered(asm.lines(), &options).map_err(|err| err.error)?;
+    let statements: Vec<Statement> = numbered.into_iter().map(|(_, s)| s).collect();
+    let layout = relax(&statements, &options).map_err(|(_, error)| error)?;
+    symbol_table(&statements, &layout.labels, &layout.origins).map_err(|(_, error)| error)?;
+    // The layout knows how many words the program takes before any is
+    // encoded.
+    let needed = layout
+        .sections
+        .iter()
+        .filter(|section| **section == Section::Text)
+        .count();
+    if needed > out.len() {
+        return Err(AssembleError::BufferTooSmall {
+            needed,
+            capacity: out.len(),
+        });
+    }
+    // Errors are found while patching, so check every word before writing
+    // any of them.
+    let text = |i: usize| layout.sections[i] == Section::Text;
+    patch_labels(
+        &layout.instructions,
+        &layout.addresses,
+        &layout.labels,
+        options.address_unit,
+        |_, _| {},
+    )
+    .map_err(|(_, error)| error)?;
+    let mut n = 0;
+    patch_labels(
+        &layout.instructions,
+        &layout.addresses,
+        &layout.labels,
+        options.address_unit,
+        |i, word| {
+            if text(i) {
+                out[n] = word;
+                n += 1;
+            }
+        },
+    )
+    .map_err(|(_, error)| error)?;
+    Ok(n)
+}
+
+/// Assemble a program and format it as the command line would by default,
+/// without touching the filesystem.
+pub fn assemble_to_bytes(asm: &str, format: OutputFormat) -> Result<Vec<u8>, AssembleError> {
+    Ok(output::emit(&assemble(asm)?, format, "PROGRAM", 32))
+}
+
+/// Assemble a whole program, also returning its symbol table.
+pub fn assemble_program(asm: &str) -> Result<Program, AssembleError> {
+    assemble_program_with(asm, &Options::default())
+}
+
+/// Options controlling how a program is assembled.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Symbols predefined for `.ifdef`/`.ifndef`, as by `-D`.
+    pub defines: HashMap<String, i64>,
+    /// Rewrite branches whose target is out of range into an inverted
+    /// branch over a `jal`, instead of failing.
+    pub relax_branches: bool,
+    /// Additional instructions, as loaded from an ISA spec.
+    pub isa: Vec<CustomInstruction>,
+    /// The byte address the `.text` section is placed at.
+    pub text_base: usize,
+    /// The byte address the `.data` section is placed at.
+    pub data_base: usize,
+    /// Skip unknown directives with a warning instead of failing, for
+    /// assembly written for other assemblers.
+    pub ignore_unknown_directives: bool,
+    /// Labels at fixed byte addresses outside the program, such as MMIO
+    /// registers. They cannot be redefined in the source.
+    pub seed_labels: HashMap<String, usize>,
+    /// Optional extensions whose instructions are accepted.
+    pub extensions: Vec<Extension>,
+    /// Reject instructions outside this base ISA.
+    pub profile: Option<Profile>,
+    /// Warn about branches and jumps to the very next instruction, which
+    /// have no effect.
+    pub warn_zero_branches: bool,
+    /// What an address counts, for memories addressed by word.
+    pub address_unit: AddressUnit,
+    /// Warn about labels that no instruction refers to, other than those
+    /// exported with `.globl` or seeded from outside the program.
+    pub warn_unused_labels: bool,
+    /// Warn about stores based on `x0` at a positive offset below this many
+    /// bytes, which usually write through a null pointer.
+    pub warn_null_stores: Option<u32>,
+    /// Warn about instructions written out with `x0` as their destination,
+    /// whose results are discarded.
+    pub warn_x0_writes: bool,
+}
+
+/// What one address step counts: a byte, or a whole word for simulators
+/// with word-addressed memory. Layout is done in bytes either way; the
+/// unit applies to the offsets and addresses patched into instructions and
+/// to the addresses reported in listings and line maps. Conditional
+/// branches are the exception: their custom encoding already counts
+/// instructions, so it is the same in both units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressUnit {
+    #[default]
+    Byte,
+    Word,
+}
+
+impl AddressUnit {
+    /// The number of bytes one address step spans.
+    fn bytes(self) -> usize {
+        match self {
+            AddressUnit::Byte => 1,
+            AddressUnit::Word => 4,
+        }
+    }
+
+    /// Express a byte address or offset in this unit.
+    fn scale(self, bytes: usize) -> usize {
+        bytes / self.bytes()
+    }
+}
+
+impl FromStr for AddressUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "byte" => Ok(AddressUnit::Byte),
+            "word" => Ok(AddressUnit::Word),
+            _ => Err(format!("Invalid Address Unit: `{}`", s)),
+        }
+    }
+}
+
+/// A base ISA that `--profile` restricts the program to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Rv32i,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rv32i" => Ok(Profile::Rv32i),
+            _ => Err(format!("Invalid Profile: `{}`", s)),
+        }
+    }
+}
+
+/// An optional ISA extension, off unless enabled with `--ext`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extension {
+    /// A subset of the Zbb bit-manipulation extension.
+    Zbb,
+}
+
+impl FromStr for Extension {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zbb" => Ok(Extension::Zbb),
+            _ => Err(format!("Invalid Extension: `{}`", s)),
+        }
+    }
+}
+
+/// An ISA string such as `rv64im` or `rv32i_zbb`, as a source file may
+/// declare on its first line with `// risc-v: rv64im`. The M and F
+/// extensions are always accepted, so the string only restricts the
+/// program when it is exactly RV32I, as `--profile rv32i` does.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IsaString {
+    pub profile: Option<Profile>,
+    pub extensions: Vec<Extension>,
+}
+
+impl FromStr for IsaString {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid ISA String: `{}`", s);
+        let mut parts = s.split('_');
+        let base = parts.next().unwrap_or_default();
+        let letters = base
+            .strip_prefix("rv32")
+            .or_else(|| base.strip_prefix("rv64"))
+            .ok_or_else(err)?;
+        if !letters.starts_with('i') || !letters.chars().all(|c| "imf".contains(c)) {
+            return Err(err());
+        }
+        let profile = if base == "rv32i" {
+            Some(Profile::Rv32i)
+        } else {
+            None
+        };
+        let extensions = parts
+            .map(|extension| extension.parse().map_err(|_| err()))
+            .collect::<Result<_, _>>()?;
+        Ok(IsaString {
+            profile,
+            extensions,
+        })
+    }
+}
+
+/// The ISA string declared by a `// risc-v: ...` comment on the first line
+/// of `asm`, if it has one.
+pub fn declared_isa(asm: &str) -> Result<Option<IsaString>, String> {
+    let first = asm.lines().next().unwrap_or_default();
+    match ISA_COMMENT_REGEX.captures(first) {
+        Some(caps) => caps[1].parse().map(Some),
+        None => Ok(None),
+    }
+}
+
+/// The largest distance in instructions a branch can encode.
+const MAX_BRANCH_DISTANCE: usize = 0b11111;
+
+/// The most bytes one `.org` or `.skip` may pad, so that a mistyped offset
+/// fails instead of exhausting memory.
+const MAX_PADDING: usize = 1 << 24;
+
+/// Assemble a whole program with the given options.
+pub fn assemble_program_with(asm: &str, options: &Options) -> Result<Program, AssembleError> {
+    assemble_program_located(asm, options).map_err(|err| err.error)
+}
+
+/// A 0-based source line, and the byte column its statement starts at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// An error together with where in the source it arose, if anywhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocatedError {
+    pub span: Option<Span>,
+    pub error: AssembleError,
+}
+
+/// Assemble a whole program with the given options, locating any error.
+pub fn assemble_program_located(asm: &str, options: &Options) -> Result<Program, LocatedError> {
+    assemble_lines(asm.lines(), options)
+}
+
+/// Assemble the lines of a whole program, locating any error.
+fn assemble_lines<'a>(
+    lines: impl IntoIterator<Item = &'a str>,
+    options: &Options,
+) -> Result<Program, LocatedError> {
+    let numbered = parse_numbered(lines, options)?;
+    let statements: Vec<Statement> = numbered.iter().map(|(_, s)| s.clone()).collect();
+    match assemble_statements(&statements, options) {
+        Ok((program, _)) => Ok(program),
+        Err((s, error)) => Err(LocatedError {
+            span: s.map(|s| numbered[s].0),
+            error,
+        }),
+    }
+}
+
+/// Where the words of each statement were placed, across both sections.
+struct Placement {
+    /// Every word, in the order laid out.
+    words: Vec<u32>,
+    /// The index of the first word laid out for each statement.
+    starts: Vec<usize>,
+    /// The byte address each statement starts at.
+    origins: Vec<usize>,
+}
+
+/// Assemble parsed statements, also returning where each statement was
+/// placed. An error is returned with the index of the statement it arose
+/// at, if any.
+fn assemble_statements(
+    statements: &[Statement],
+    options: &Options,
+) -> Result<(Program, Placement), (Option<usize>, AssembleError)> {
+    let Layout {
+        instructions,
+        addresses,
+        sections,
+        labels,
+        starts,
+        origins,
+        ..
+    } = relax(statements, options).map_err(|(s, error)| (Some(s), error))?;
+
+    // Pseudo-instructions such as `j` write `x0` on purpose, so only
+    // instructions written out by the user are checked.
+    let mut warnings = Vec::new();
+    for statement in statements {
+        if let Statement::UnknownDirective(directive) = statement {
+            warnings.push(format!("ignoring unknown directive `{}`", directive));
+        }
+        if let Statement::Instruction(inst) = statement {
+            if options.warn_x0_writes && inst.rd() == Some(0) && !discards_on_purpose(inst) {
+                warnings.push(format!(
+                    "`{}` writes to `x0`, so its result is discarded",
+                    inst.mnemonic()
+                ));
+            }
+            if let Instruction::R {
+                op: ROp::Div | ROp::Divu | ROp::Rem | ROp::Remu,
+                rs2: 0,
+                ..
+            } = inst
+            {
+                warnings.push(format!(
+                    "`{}` divides by `x0`, which always gives the divide-by-zero result",
+                    inst.mnemonic()
+                ));
+            }
+            if let (
+                Some(threshold),
+                Instruction::Store {
+                    rs1: 0,
+                    imm: Immediate::Value(offset),
+                    ..
+                },
+            ) = (options.warn_null_stores, inst)
+            {
+                if *offset > 0 && (*offset as u32) < threshold {
+                    warnings.push(format!(
+                        "`{}` stores to {:#x} from `x0`, which is likely a null pointer",
+                        inst.mnemonic(),
+                        offset
+                    ));
+                }
+            }
+        }
+    }
+    let symbols = symbol_table(statements, &labels, &origins)?;
+    if options.warn_zero_branches {
+        for (i, (_, relocation)) in instructions.iter().enumerate() {
+            if let Some(Relocation::Branch(label) | Relocation::Jump(label)) = relocation {
+                let target = labels.resolve(label, i).map(|target| target.address);
+                // Self-loops are deliberate, as in a halt loop, so only a
+                // branch to the next instruction is flagged.
+                if target == Some(addresses[i] + 4) {
+                    warnings.push(format!(
+                        "the branch to `{}` at {:#x} goes to the next instruction",
+                        label, addresses[i]
+                    ));
+                }
+            }
+        }
+    }
+    if options.warn_unused_labels {
+        let referenced: HashSet<&str> = instructions
+            .iter()
+            .filter_map(|(_, relocation)| relocation.as_ref().map(Relocation::label))
+            .collect();
+        for (label, symbol) in &symbols {
+            if !symbol.global
+                && !referenced.contains(label.as_str())
+                && !options.seed_labels.contains_key(label)
+            {
+                warnings.push(format!("the label `{}` is never referenced", label));
+            }
+        }
+    }
+    let words = transform_labels(instructions, &addresses, labels, options.address_unit).map_err(
+        |(i, error)| {
+            // The last statement laid out at or before the instruction is the
+            // one that produced it.
+            let s = starts.partition_point(|start| *start <= i);
+            (s.checked_sub(1), error)
+        },
+    )?;
+    let in_section = |section: Section| {
+        words
+            .iter()
+            .zip(&sections)
+            .filter(|(_, s)| **s == section)
+            .map(|(word, _)| *word)
+            .collect()
+    };
+    let program = Program {
+        instructions: in_section(Section::Text),
+        data: in_section(Section::Data),
+        symbols,
+        warnings,
+    };
+    let placement = Placement {
+        words,
+        starts,
+        origins,
+    };
+    Ok((program, placement))
+}
+
+/// The symbol table of a laid-out program, checking that every label named
+/// by `.globl`, `.type` and `.size` is defined.
+fn symbol_table(
+    statements: &[Statement],
+    labels: &Labels,
+    origins: &[usize],
+) -> Result<BTreeMap<String, Symbol>, (Option<usize>, AssembleError)> {
+    let mut globals: HashSet<String> = statements
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::Global(label) => Some(label.clone()),
+            _ => None,
+        })
+        .collect();
+    let mut symbols = BTreeMap::new();
+    for (label, position) in &labels.global {
+        let global = globals.remove(label);
+        let symbol = Symbol {
+            address: position.address,
+            global,
+            kind: None,
+            size: None,
+        };
+        symbols.insert(label.clone(), symbol);
+    }
+    if let Some(label) = globals.into_iter().min() {
+        let s = statements
+            .iter()
+            .position(|statement| *statement == Statement::Global(label.clone()));
+        let suggestion = suggest_label(&label, &labels.global);
+        return Err((s, AssembleError::InvalidLabel { label, suggestion }));
+    }
+    for (s, statement) in statements.iter().enumerate() {
+        let label = match statement {
+            Statement::Type(label, _) | Statement::Size(label, _) => label,
+            _ => continue,
+        };
+        let invalid = |label: &String| {
+            let suggestion = suggest_label(label, &labels.global);
+            (
+                Some(s),
+                AssembleError::InvalidLabel {
+                    label: label.clone(),
+                    suggestion,
+                },
+            )
+        };
+        let symbol = symbols.get_mut(label).ok_or_else(|| invalid(label))?;
+        match statement {
+            Statement::Type(_, kind) => symbol.kind = Some(*kind),
+            Statement::Size(_, SymbolSize::Bytes(bytes)) => symbol.size = Some(*bytes),
+            Statement::Size(_, SymbolSize::Since(since)) => {
+                // `.-since` measures from `since` to the directive.
+                let start = labels
+                    .global
+                    .get(since)
+                    .ok_or_else(|| invalid(since))?
+                    .address;
+                symbol.size = Some(origins[s].saturating_sub(start));
+            }
+            _ => {}
+        }
+    }
+    Ok(symbols)
+}
+
+/// Whether `inst` writes `x0` as a well-known idiom rather than by mistake.
+/// So far that is `addi x0, x0, 0`, the canonical `nop`, and `jal x0`, the
+/// jump `j` spells out; discarding CSR reads would join them once CSR
+/// instructions are supported.
+fn discards_on_purpose(inst: &Instruction) -> bool {
+    matches!(
+        inst,
+        Instruction::I {
+            op: IOp::Addi,
+            rd: 0,
+            rs1: 0,
+            imm: Immediate::Value(0),
+        } | Instruction::Jal { rd: 0, .. }
+    )
+}
+
+/// Lay out `statements`, relaxing far branches if `options` asks to.
+fn relax(statements: &[Statement], options: &Options) -> Result<Layout, (usize, AssembleError)> {
+    // Relaxing a branch grows the program and may push other branches out
+    // of range, so relax until no branch is too far.
+    let mut relaxed = HashSet::new();
+    loop {
+        let layout = layout(statements, &relaxed, options)?;
+        if !options.relax_branches {
+            return Ok(layout);
+        }
+        let far: Vec<usize> = layout
+            .branches
+            .iter()
+            .filter(|(_, i)| match &layout.instructions[*i].1 {
+                Some(relocation) => match layout.labels.resolve(relocation.label(), *i) {
+                    Some(j) => {
+                        let address = layout.addresses[*i];
+                        address.max(j.address) - address.min(j.address) > MAX_BRANCH_DISTANCE * 4
+                    }
+                    None => false,
+                },
+                None => false,
+            })
+            .map(|(statement, _)| *statement)
+            .collect();
+        if far.is_empty() {
+            return Ok(layout);
+        }
+        relaxed.extend(far);
+    }
+}
+
+/// The relocations still pending once `asm` is laid out, with the index of
+/// the instruction each patches, before any label is resolved.
+pub fn relocations(
+    asm: &str,
+    options: &Options,
+) -> Result<Vec<(usize, Relocation)>, AssembleError> {
+    let statements = parse_with(asm, options)?;
+    let layout = relax(&statements, options).map_err(|(_, error)| error)?;
+    Ok(layout
+        .instructions
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, (_, relocation))| relocation.map(|relocation| (i, relocation)))
+        .collect())
+}
+
+/// A relocation still pending once a program is laid out, placed for an
+/// object file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingRelocation {
+    pub section: Section,
+    /// The byte offset of the patched word within its section.
+    pub offset: usize,
+    /// The word before it is patched.
+    pub word: u32,
+    pub relocation: Relocation,
+    /// The section offset of the `auipc` the low half of a PC-relative pair
+    /// refers to, if it can be found.
+    pub pair: Option<usize>,
+}
+
+/// The relocations still pending once `asm` is laid out, placed by section.
+/// Labels need not be defined, as for symbols in another object.
+pub fn pending_relocations(
+    asm: &str,
+    options: &Options,
+) -> Result<Vec<PendingRelocation>, AssembleError> {
+    let statements = parse_with(asm, options)?;
+    let layout = relax(&statements, options).map_err(|(_, error)| error)?;
+    let offset = |i: usize| layout.addresses[i] - layout.bases[layout.sections[i] as usize];
+    let mut pending = Vec::new();
+    for (i, (word, relocation)) in layout.instructions.iter().enumerate() {
+        let relocation = match relocation {
+            Some(relocation) => relocation.clone(),
+            None => continue,
+        };
+        let pair = match &relocation {
+            Relocation::PcrelLo(label) => layout
+                .labels
+                .resolve(label, i)
+                .filter(|target| target.index < layout.instructions.len())
+                .map(|target| offset(target.index)),
+            Relocation::PcrelPairLo(_) => Some(offset(i - 1)),
+            _ => None,
+        };
+        pending.push(PendingRelocation {
+            section: layout.sections[i],
+            offset: offset(i),
+            word: *word,
+            relocation,
+            pair,
+        });
+    }
+    Ok(pending)
+}
+
+/// Lay out a program without resolving labels, leaving every relocated
+/// field zero for a linker to patch with `pending_relocations`. Only the
+/// words of the program are set.
+pub fn assemble_unresolved(asm: &str, options: &Options) -> Result<Program, AssembleError> {
+    let statements = parse_with(asm, options)?;
+    let layout = relax(&statements, options).map_err(|(_, error)| error)?;
+    let in_section = |section: Section| {
+        layout
+            .instructions
+            .iter()
+            .zip(&layout.sections)
+            .filter(|(_, s)| **s == section)
+            .map(|((word, _), _)| *word)
+            .collect()
+    };
+    Ok(Program {
+        instructions: in_section(Section::Text),
+        data: in_section(Section::Data),
+        symbols: BTreeMap::new(),
+        warnings: Vec::new(),
+    })
+}
+
+/// The addresses of the instructions referring to each label, for every
+/// label the program defines, sorted by name. Numeric local labels have no
+/// single name, so they are left out.
+pub fn cross_references(
+    asm: &str,
+    options: &Options,
+) -> Result<BTreeMap<String, Vec<usize>>, AssembleError> {
+    let statements = parse_with(asm, options)?;
+    let layout = relax(&statements, options).map_err(|(_, error)| error)?;
+    let mut references: BTreeMap<String, Vec<usize>> = layout
+        .labels
+        .global
+        .keys()
+        .map(|label| (label.clone(), Vec::new()))
+        .collect();
+    for ((_, relocation), address) in layout.instructions.iter().zip(&layout.addresses) {
+        if let Some(addresses) = relocation
+            .as_ref()
+            .and_then(|relocation| references.get_mut(relocation.label()))
+        {
+            addresses.push(options.address_unit.scale(*address));
+        }
+    }
+    Ok(references)
+}
+
+/// The indices in `.text` of the words that branches and jumps go to.
+pub fn branch_targets(asm: &str, options: &Options) -> Result<HashSet<usize>, AssembleError> {
+    let statements = parse_with(asm, options)?;
+    let layout = relax(&statements, options).map_err(|(_, error)| error)?;
+    let mut targets = HashSet::new();
+    for (i, (_, relocation)) in layout.instructions.iter().enumerate() {
+        if let Some(Relocation::Branch(label) | Relocation::Jump(label)) = relocation {
+            let target = match layout.labels.resolve(label, i) {
+                Some(target) if layout.sections.get(target.index) == Some(&Section::Text) => {
+                    target.index
+                }
+                _ => continue,
+            };
+            let before = &layout.sections[..target];
+            targets.insert(before.iter().filter(|s| **s == Section::Text).count());
+        }
+    }
+    Ok(targets)
+}
+
+/// Map every source line that produces code or data to the byte address of
+/// its first word, as 1-based line numbers in source order.
+pub fn line_addresses(asm: &str, options: &Options) -> Result<Vec<(usize, usize)>, AssembleError> {
+    let numbered = parse_numbered(asm.lines(), options).map_err(|err| err.error)?;
+    let statements: Vec<Statement> = numbered.iter().map(|(_, s)| s.clone()).collect();
+    let (_, Placement { origins, .. }) =
+        assemble_statements(&statements, options).map_err(|(_, error)| error)?;
+    let mut lines: Vec<(usize, usize)> = Vec::new();
+    for ((span, statement), origin) in numbered.iter().zip(origins) {
+        let produces = matches!(
+            statement,
+            Statement::Instruction(_) | Statement::Pseudo(_) | Statement::Bytes(_)
+        );
+        // A macro invocation produces several statements from one line.
+        if produces && lines.last().is_none_or(|(line, _)| *line != span.line + 1) {
+            lines.push((span.line + 1, options.address_unit.scale(origin)));
+        }
+    }
+    Ok(lines)
+}
+
+/// Produce a listing of `asm` with the address and encoding of every line.
+/// Lines that expand into several instructions, such as pseudo-instructions
+/// and relaxed branches, are followed by each instruction indented beneath.
+pub fn listing(asm: &str, options: &Options) -> Result<String, AssembleError> {
+    let numbered = parse_numbered(asm.lines(), options).map_err(|err| err.error)?;
+    let statements: Vec<Statement> = numbered.iter().map(|(_, s)| s.clone()).collect();
+    let (
+        _,
+        Placement {
+            words: all,
+            starts,
+            origins,
+        },
+    ) = assemble_statements(&statements, options).map_err(|(_, error)| error)?;
+    // The statements parsed from each line; a macro invocation has several.
+    let mut lines: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (k, (span, _)) in numbered.iter().enumerate() {
+        lines.entry(span.line).or_default().push(k);
+    }
+    let mut listing = String::new();
+    for (n, source) in asm.lines().enumerate() {
+        let source = source.trim_end();
+        let ks = match lines.get(&n) {
+            Some(ks) => ks,
+            None => {
+                listing.push_str(&format!("{:>4} {:8} {:8}  {}\n", n + 1, "", "", source));
+                continue;
+            }
+        };
+        let start = starts[ks[0]];
+        let origin = origins[ks[0]];
+        let address = |offset| options.address_unit.scale(origin + offset);
+        let words: &[u32] = match ks.as_slice() {
+            [k] if !matches!(
+                statements[*k],
+                Statement::Instruction(_) | Statement::Pseudo(_) | Statement::Bytes(_)
+            ) =>
+            {
+                &[]
+            }
+            _ => {
+                let last = ks[ks.len() - 1];
+                let end = starts.get(last + 1).copied().unwrap_or(all.len());
+                &all[start..end]
+            }
+        };
+        if ks
+            .iter()
+            .all(|k| matches!(statements[*k], Statement::Bytes(_)))
+        {
+            // Data is listed as raw words rather than disassembled. Bytes
+            // packed into the word of the line before show up there.
+            let aligned = |i: usize| {
+                options
+                    .address_unit
+                    .scale(origin.next_multiple_of(4) + i * 4)
+            };
+            match words.split_first() {
+                Some((word, rest)) => {
+                    listing.push_str(&format!(
+                        "{:>4} {:08x} {:08x}  {}\n",
+                        n + 1,
+                        aligned(0),
+                        word,
+                        source
+                    ));
+                    for (i, word) in rest.iter().enumerate() {
+                        listing.push_str(&format!(
+                            "{:4} {:08x} {:08x}\n",
+                            "",
+                            aligned(i + 1),
+                            word
+                        ));
+                    }
+                }
+                None => listing.push_str(&format!(
+                    "{:>4} {:08x} {:8}  {}\n",
+                    n + 1,
+                    address(0),
+                    "",
+                    source
+                )),
+            }
+            continue;
+        }
+        match words {
+            [word] => listing.push_str(&format!(
+                "{:>4} {:08x} {:08x}  {}\n",
+                n + 1,
+                address(0),
+                word,
+                source
+            )),
+            _ => {
+                listing.push_str(&format!(
+                    "{:>4} {:08x} {:8}  {}\n",
+                    n + 1,
+                    address(0),
+                    "",
+                    source
+                ));
+                for (i, word) in words.iter().enumerate() {
+                    let inst = disassemble::decode_one(start + i, *word, RegNames::Numeric);
+                    listing.push_str(&format!(
+                        "{:4} {:08x} {:08x}      {}\n",
+                        "",
+                        address(i * 4),
+                        word,
+                        inst
+                    ));
+                }
+            }
+        }
+    }
+    Ok(listing)
+}
+
+/// Instruction words with their pending relocations.
+struct Layout {
+    instructions: Vec<(u32, Option<Relocation>)>,
+    /// The byte address of each instruction.
+    addresses: Vec<usize>,
+    /// The section of each instruction.
+    sections: Vec<Section>,
+    labels: Labels,
+    /// The statement and instruction index of each branch not relaxed.
+    branches: Vec<(usize, usize)>,
+    /// The index of the first instruction laid out for each statement.
+    starts: Vec<usize>,
+    /// The byte address each statement starts at.
+    origins: Vec<usize>,
+    /// The section being laid out, with the base address and size in bytes
+    /// of the text and data sections.
+    section: Section,
+    bases: [usize; 2],
+    sizes: [usize; 2],
+    /// For each section whose last word is partly filled by data, the index
+    /// of that word and how many of its bytes are used.
+    open: [Option<(usize, usize)>; 2],
+}
+
+impl Layout {
+    /// The byte address the next instruction is placed at.
+    fn address(&self) -> usize {
+        let s = self.section as usize;
+        let unused = self.open[s].map_or(0, |(_, used)| 4 - used);
+        self.bases[s] + self.sizes[s] - unused
+    }
+
+    /// Start a new word, leaving the rest of any partly filled one zero.
+    fn push(&mut self, word: u32, relocation: Option<Relocation>) {
+        self.open[self.section as usize] = None;
+        self.addresses.push(self.address());
+        self.sections.push(self.section);
+        self.instructions.push((word, relocation));
+        self.sizes[self.section as usize] += 4;
+    }
+
+    /// Start `words` zero words at once, for padding.
+    fn push_zeros(&mut self, words: usize) {
+        let s = self.section as usize;
+        self.open[s] = None;
+        let start = self.address();
+        self.addresses.extend((0..words).map(|k| start + 4 * k));
+        self.sections
+            .resize(self.sections.len() + words, self.section);
+        self.instructions
+            .resize(self.instructions.len() + words, (0, None));
+        self.sizes[s] += 4 * words;
+    }
+
+    /// Place a data byte after the last one, packing it into the last word
+    /// while that word has room.
+    fn push_byte(&mut self, byte: u8) {
+        let s = self.section as usize;
+        match self.open[s] {
+            Some((i, used)) => {
+                self.instructions[i].0 |= (byte as u32) << (8 * used);
+                self.open[s] = Some((i, used + 1)).filter(|(_, used)| *used < 4);
+            }
+            None => {
+                self.push(byte as u32, None);
+                self.open[s] = Some((self.instructions.len() - 1, 1));
+            }
+        }
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            index: self.instructions.len(),
+            address: self.address(),
+        }
+    }
+}
+
+/// Lay out `statements`, expanding the branches at the statement indices in
+/// `relaxed` into an inverted branch over a `jal`. An error is returned with
+/// the index of the statement it arose at.
+fn layout(
+    statements: &[Statement],
+    relaxed: &HashSet<usize>,
+    options: &Options,
+) -> Result<Layout, (usize, AssembleError)> {
+    let mut layout = Layout {
+        instructions: Vec::new(),
+        addresses: Vec::new(),
+        sections: Vec::new(),
+        labels: Labels::default(),
+        branches: Vec::new(),
+        starts: Vec::new(),
+        origins: Vec::new(),
+        section: Section::Text,
+        bases: [options.text_base, options.data_base],
+        sizes: [0, 0],
+        open: [None, None],
+    };
+    for (label, address) in &options.seed_labels {
+        let position = Position {
+            index: usize::MAX,
+            address: *address,
+        };
+        layout.labels.insert(label.clone(), position);
+    }
+    for (s, statement) in statements.iter().enumerate() {
+        layout.starts.push(layout.instructions.len());
+        layout.origins.push(layout.address());
+        match statement {
+            Statement::Instruction(Instruction::Branch {
+                op,
+                rs1,
+                rs2,
+                label,
+            }) if relaxed.contains(&s) => {
+                let skip = Instruction::Branch {
+                    op: op.inverse(),
+                    rs1: *rs1,
+                    rs2: *rs2,
+                    label: label.clone(),
+                };
+                let jal = Instruction::Jal {
+                    rd: 0,
+                    label: label.clone(),
+                };
+                layout.push(skip.encode() | (2 << 7), None);
+                layout.push(jal.encode(), jal.relocation());
+            }
+            Statement::Instruction(inst) => {
+                if let Instruction::Branch { .. } = inst {
+                    layout.branches.push((s, layout.instructions.len()));
+                }
+                layout.push(inst.encode(), inst.relocation());
+            }
+            Statement::Pseudo(insts) => {
+                for inst in insts {
+                    layout.push(inst.encode(), inst.relocation());
+                }
+            }
+            Statement::Label(label) => {
+                if options.seed_labels.contains_key(label) {
+                    return Err((s, AssembleError::DuplicateLabel(label.clone())));
+                }
+                // A label on code is word-aligned like the code, while one on
+                // data may fall inside a word.
+                let next = statements[s + 1..].iter().find(|statement| {
+                    !matches!(
+                        statement,
+                        Statement::Label(_)
+                            | Statement::Global(_)
+                            | Statement::Type(..)
+                            | Statement::Size(..)
+                    )
+                });
+                if !matches!(next, Some(Statement::Bytes(_))) {
+                    layout.open[layout.section as usize] = None;
+                }
+                let position = layout.position();
+                layout.labels.insert(label.clone(), position);
+            }
+            Statement::Org(requested) => {
+                let requested = *requested;
+                let current = layout.sizes[layout.section as usize];
+                if requested < current {
+                    return Err((s, AssembleError::BackwardOrigin { current, requested }));
+                }
+                if !requested.is_multiple_of(4) {
+                    return Err((s, AssembleError::UnalignedOrigin(requested)));
+                }
+                let padding = requested - current;
+                if padding > MAX_PADDING {
+                    return Err((s, AssembleError::PaddingTooLarge(padding)));
+                }
+                layout.push_zeros(padding / 4);
+            }
+            Statement::Skip(count) => {
+                if !count.is_multiple_of(4) {
+                    return Err((s, AssembleError::UnalignedSkip(*count)));
+                }
+                if *count > MAX_PADDING {
+                    return Err((s, AssembleError::PaddingTooLarge(*count)));
+                }
+                layout.push_zeros(count / 4);
+            }
+            Statement::Bytes(bytes) => {
+                for byte in bytes {
+                    layout.push_byte(*byte);
+                }
+            }
+            Statement::Global(_)
+            | Statement::Type(..)
+            | Statement::Size(..)
+            | Statement::UnknownDirective(_) => {}
+            Statement::Section(section) => layout.section = *section,
+        }
+    }
+    Ok(layout)
+}
+
+/// Assemble a single instruction line. Lines that need label resolution,
+/// such as branches, are rejected with `AssembleError::UnresolvedLabel`.
+pub fn assemble_one(line: &str) -> Result<Vec<u32>, AssembleError> {
+    match parse_line(line)? {
+        None => Ok(Vec::new()),
+        Some(Statement::Instruction(inst)) => encode_all(&[inst]),
+        Some(Statement::Pseudo(insts)) => encode_all(&insts),
+        Some(_) => Err(AssembleError::InvalidInstruction(line.trim().to_string())),
+    }
+}
+
+fn encode_all(insts: &[Instruction]) -> Result<Vec<u32>, AssembleError> {
+    insts
+        .iter()
+        .map(|inst| match inst.relocation() {
+            None => Ok(inst.encode()),
+            Some(relocation) => Err(AssembleError::UnresolvedLabel(
+                relocation.label().to_string(),
+            )),
+        })
+        .collect()
+}
+
+/// The number of the register named `name`, either `x0`..`x31` or an ABI
+/// name such as `sp` or `a0`.
+pub fn resolve_register(name: &str) -> Option<u32> {
+    if let Some(number) = name.strip_prefix('x') {
+        if !number.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        return number.parse().ok().filter(|number| *number < 32);
+    }
+    match name {
+        "fp" => Some(8),
+        _ => ABI_NAMES
+            .iter()
+            .position(|abi| *abi == name)
+            .map(|i| i as u32),
+    }
+}
+
+/// The number of the floating-point register named `name`, either
+/// `f0`..`f31` or an ABI name such as `fa0` or `fs1`.
+pub fn resolve_fp_register(name: &str) -> Option<u32> {
+    if let Some(number) = name.strip_prefix('f') {
+        if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) {
+            return number.parse().ok().filter(|number| *number < 32);
+        }
+    }
+    FP_ABI_NAMES
+        .iter()
+        .position(|abi| *abi == name)
+        .map(|i| i as u32)
+}
+
+fn fp_register(name: &str) -> Result<u32, AssembleError> {
+    resolve_fp_register(name).ok_or_else(|| AssembleError::InvalidRegister(name.to_string()))
+}
+
+pub(crate) fn register(name: &str) -> Result<u32, AssembleError> {
+    resolve_register(name).ok_or_else(|| match resolve_fp_register(name) {
+        Some(_) => AssembleError::FpRegister(name.to_string()),
+        None => AssembleError::InvalidRegister(name.to_string()),
+    })
+}
+
+pub(crate) fn parse_immediate(imm: &str) -> Result<i64, AssembleError> {
+    parse_wide(imm)?
+        .try_into()
+        .map_err(|_| AssembleError::InvalidImmediate(imm.to_string()))
+}
+
+/// Parse an immediate too wide for `i64`, such as the unsigned values
+/// `.8byte` takes.
+fn parse_wide(imm: &str) -> Result<i128, AssembleError> {
+    if let Some(literal) = imm.strip_prefix('\'') {
+        return parse_char(literal.strip_suffix('\'').unwrap_or(literal))
+            .map(|c| c as i128)
+            .ok_or_else(|| AssembleError::InvalidImmediate(imm.to_string()));
+    }
+    let (negative, digits) = match imm.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, imm),
+    };
+    let (radix, digits) = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => (16, hex),
+        None => (10, digits),
+    };
+    let err = || AssembleError::InvalidImmediate(imm.to_string());
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err(err());
+    }
+    let value = i128::from_str_radix(&digits.replace('_', ""), radix).map_err(|_| err())?;
+    Ok(if negative { -value } else { value })
+}
+
+/// Evaluate a constant expression of numbers and `symbols`, with `+`, `-`,
+/// `*`, `/`, `<<`, `>>`, `&`, `|` and parentheses, binding as in C.
+fn evaluate(expr: &str, symbols: &HashMap<String, i64>) -> Result<i64, AssembleError> {
+    let err = || AssembleError::InvalidImmediate(expr.to_string());
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_alphanumeric() || c == '_' {
+            rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len())
+        } else if rest.starts_with("<<") || rest.starts_with(">>") {
+            2
+        } else {
+            1
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    let mut tokens = tokens.into_iter().peekable();
+    let value = evaluate_binary(&mut tokens, symbols, 0).ok_or_else(err)?;
+    match tokens.next() {
+        Some(_) => Err(err()),
+        None => Ok(value),
+    }
+}
+
+/// The binary operators of `evaluate`, loosest first.
+const OPERATORS: &[&[&str]] = &[&["|"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/"]];
+
+/// Evaluate operators binding at least as tightly as `OPERATORS[level]`.
+fn evaluate_binary<'a>(
+    tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a str>>,
+    symbols: &HashMap<String, i64>,
+    level: usize,
+) -> Option<i64> {
+    if level == OPERATORS.len() {
+        return evaluate_unary(tokens, symbols);
+    }
+    let mut value = evaluate_binary(tokens, symbols, level + 1)?;
+    while let Some(op) = tokens.next_if(|token| OPERATORS[level].contains(token)) {
+        let rhs = evaluate_binary(tokens, symbols, level + 1)?;
+        value = match op {
+            "|" => value | rhs,
+            "&" => value & rhs,
+            "<<" => value.checked_shl(rhs.try_into().ok()?)?,
+            ">>" => value.checked_shr(rhs.try_into().ok()?)?,
+            "+" => value.checked_add(rhs)?,
+            "-" => value.checked_sub(rhs)?,
+            "*" => value.checked_mul(rhs)?,
+            _ => value.checked_div(rhs)?,
+        };
+    }
+    Some(value)
+}
+
+fn evaluate_unary<'a>(
+    tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a str>>,
+    symbols: &HashMap<String, i64>,
+) -> Option<i64> {
+    match tokens.next()? {
+        "-" => evaluate_unary(tokens, symbols)?.checked_neg(),
+        "(" => {
+            let value = evaluate_binary(tokens, symbols, 0)?;
+            tokens.next_if_eq(&")")?;
+            Some(value)
+        }
+        token => match symbols.get(token) {
+            Some(value) => Some(*value),
+            None => parse_immediate(token).ok(),
+        },
+    }
+}
+
+/// Parse the body of a character literal, which must be a single character
+/// after escapes are processed.
+fn parse_char(literal: &str) -> Option<char> {
+    let mut chars = unescape(literal)?.into_iter();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+/// Process the escapes in the body of a character or string literal.
+fn unescape(literal: &str) -> Option<Vec<char>> {
+    let mut chars = literal.chars();
+    let mut unescaped = Vec::new();
+    while let Some(c) = chars.next() {
+        unescaped.push(match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                c @ ('\\' | '\'' | '"') => c,
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(unescaped)
+}
+
+/// The code of `line` before any `//` comment, ignoring `//` inside string
+/// and character literals.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if line[i..].starts_with("//") => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+fn parse_custom(
+    isa: &[CustomInstruction],
+    line: &str,
+) -> Result<Option<Instruction>, AssembleError> {
+    for custom in isa {
+        if let Some(inst) = custom.parse(line)? {
+            return Ok(Some(inst));
+        }
+    }
+    Ok(None)
+}
+
+fn parse_extensions(
+    extensions: &[Extension],
+    line: &str,
+) -> Result<Option<Instruction>, AssembleError> {
+    if extensions.contains(&Extension::Zbb) {
+        return parse_zbb(line);
+    }
+    Ok(None)
+}
+
+fn parse_zbb(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = ZBB_REGEX.captures(line) {
+        let op = match &caps[1] {
+            "andn" => ROp::Andn,
+            "orn" => ROp::Orn,
+            "xnor" => ROp::Xnor,
+            "min" => ROp::Min,
+            "minu" => ROp::Minu,
+            "max" => ROp::Max,
+            _ => ROp::Maxu,
+        };
+        let rd = register(&caps[2])?;
+        let rs1 = register(&caps[3])?;
+        let rs2 = register(&caps[4])?;
+        Ok(Some(Instruction::R { op, rd, rs1, rs2 }))
+    } else if let Some(caps) = ZBB_UNARY_REGEX.captures(line) {
+        let op = match &caps[1] {
+            "clz" => UnaryOp::Clz,
+            "ctz" => UnaryOp::Ctz,
+            _ => UnaryOp::Cpop,
+        };
+        let rd = register(&caps[2])?;
+        let rs1 = register(&caps[3])?;
+        Ok(Some(Instruction::Unary { op, rd, rs1 }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn load_op(mnemonic: &str) -> LoadOp {
+    if mnemonic == "lw" {
+        LoadOp::Lw
+    } else {
+        LoadOp::Ld
+    }
+}
+
+/// Parse `ld` or `lw` with an offset or the `%lo` of a label.
+fn parse_load(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = LOAD_REGEX.captures(line) {
+        let rd = register(&caps[2])?;
+        let imm = match caps.get(3) {
+            Some(imm) => offset(imm.as_str())?,
+            None => Immediate::Relocation(Relocation::Lo(caps[4].to_string())),
+        };
+        let rs1 = register(&caps[5])?;
+        Ok(Some(Instruction::Load {
+            op: load_op(&caps[1]),
+            rd,
+            rs1,
+            imm,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parse `ld`/`lw` of a label, which loads through `rd` from the label's
+/// absolute address: `lui rd, %hi(label)`, then the load from
+/// `%lo(label)(rd)`.
+fn parse_load_symbol(line: &str) -> Result<Option<Vec<Instruction>>, AssembleError> {
+    if let Some(caps) = LOAD_SYMBOL_REGEX.captures(line) {
+        let rd = register(&caps[2])?;
+        let label = caps[3].to_string();
+        Ok(Some(vec![
+            Instruction::U {
+                op: UOp::Lui,
+                rd,
+                imm: Immediate::Relocation(Relocation::Hi(label.clone())),
+            },
+            Instruction::Load {
+                op: load_op(&caps[1]),
+                rd,
+                rs1: rd,
+                imm: Immediate::Relocation(Relocation::Lo(label)),
+            },
+        ]))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parse the offset of a load or store, which must fit in 12 signed bits.
+fn offset(imm: &str) -> Result<Immediate, AssembleError> {
+    let value = parse_immediate(imm)?;
+    if !(-2048..=2047).contains(&value) {
+        return Err(AssembleError::InvalidImmediate(imm.to_string()));
+    }
+    Ok(Immediate::Value(value as i32))
+}
+
+/// Parse an F-extension load, store or arithmetic instruction.
+fn parse_f(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = FLW_REGEX.captures(line) {
+        let rd = fp_register(&caps[1])?;
+        let imm = offset(&caps[2])?;
+        let rs1 = register(&caps[3])?;
+        let op = LoadOp::Flw;
+        Ok(Some(Instruction::Load { op, rd, rs1, imm }))
+    } else if let Some(caps) = FSW_REGEX.captures(line) {
+        let rs2 = fp_register(&caps[1])?;
+        let imm = offset(&caps[2])?;
+        let rs1 = register(&caps[3])?;
+        let op = StoreOp::Fsw;
+        Ok(Some(Instruction::Store { op, rs1, rs2, imm }))
+    } else if let Some(caps) = FR_REGEX.captures(line) {
+        let op = match &caps[1] {
+            "fadd" => FOp::Fadd,
+            "fsub" => FOp::Fsub,
+            "fmul" => FOp::Fmul,
+            _ => FOp::Fdiv,
+        };
+        let rm = match caps.get(5).map(|rm| rm.as_str()) {
+            Some("rne") => RoundingMode::Rne,
+            Some("rtz") => RoundingMode::Rtz,
+            Some("rdn") => RoundingMode::Rdn,
+            Some("rup") => RoundingMode::Rup,
+            Some("rmm") => RoundingMode::Rmm,
+            _ => RoundingMode::Dyn,
+        };
+        Ok(Some(Instruction::FR {
+            op,
+            rd: fp_register(&caps[2])?,
+            rs1: fp_register(&caps[3])?,
+            rs2: fp_register(&caps[4])?,
+            rm,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parse `sd rs2, imm(rs1)`. As in the ISA manual, the first operand is the
+/// value stored, `rs2`, and the register in parentheses is the base
+/// address, `rs1`.
+fn parse_sd(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = SD_REGEX.captures(line) {
+        let rs2 = register(&caps[1])?;
+        let imm = match caps.get(2) {
+            Some(imm) => offset(imm.as_str())?,
+            None => Immediate::Relocation(Relocation::Lo(caps[3].to_string())),
+        };
+        let rs1 = register(&caps[4])?;
+        Ok(Some(Instruction::Store {
+            op: StoreOp::Sd,
+            rs1,
+            rs2,
+            imm,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_r(regex: &Regex, op: ROp, line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = regex.captures(line) {
+        let rd = register(&caps[1])?;
+        let rs1 = register(&caps[2])?;
+        let rs2 = register(&caps[3])?;
+        Ok(Some(Instruction::R { op, rd, rs1, rs2 }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_and(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    parse_r(&AND_REGEX, ROp::And, line)
+}
+
+fn parse_or(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    parse_r(&OR_REGEX, ROp::Or, line)
+}
+
+fn parse_add(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    parse_r(&ADD_REGEX, ROp::Add, line)
+}
+
+fn parse_sub(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    parse_r(&SUB_REGEX, ROp::Sub, line)
+}
+
+fn parse_slt(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    parse_r(&SLT_REGEX, ROp::Slt, line)
+}
+
+fn parse_sltu(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    parse_r(&SLTU_REGEX, ROp::Sltu, line)
+}
+
+/// Parse `sltz`, `sgtz`, `sgt` or `sgtu`, which expand to `slt`/`sltu`
+/// with reordered or zero operands.
+fn parse_comparison(line: &str) -> Result<Option<Vec<Instruction>>, AssembleError> {
+    let (op, rd, rs1, rs2) = if let Some(caps) = SLTZ_REGEX.captures(line) {
+        (ROp::Slt, register(&caps[1])?, register(&caps[2])?, 0)
+    } else if let Some(caps) = SGTZ_REGEX.captures(line) {
+        (ROp::Slt, register(&caps[1])?, 0, register(&caps[2])?)
+    } else if let Some(caps) = SGT_REGEX.captures(line) {
+        let (rs, rt) = (register(&caps[2])?, register(&caps[3])?);
+        (ROp::Slt, register(&caps[1])?, rt, rs)
+    } else if let Some(caps) = SGTU_REGEX.captures(line) {
+        let (rs, rt) = (register(&caps[2])?, register(&caps[3])?);
+        (ROp::Sltu, register(&caps[1])?, rt, rs)
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(vec![Instruction::R { op, rd, rs1, rs2 }]))
+}
+
+/// Parse an M-extension multiply or divide.
+fn parse_m(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = M_REGEX.captures(line) {
+        let op = match &caps[1] {
+            "mul" => ROp::Mul,
+            "mulh" => ROp::Mulh,
+            "mulhsu" => ROp::Mulhsu,
+            "mulhu" => ROp::Mulhu,
+            "div" => ROp::Div,
+            "divu" => ROp::Divu,
+            "rem" => ROp::Rem,
+            _ => ROp::Remu,
+        };
+        let rd = register(&caps[2])?;
+        let rs1 = register(&caps[3])?;
+        let rs2 = register(&caps[4])?;
+        Ok(Some(Instruction::R { op, rd, rs1, rs2 }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_branch(
+    regex: &Regex,
+    op: BranchOp,
+    line: &str,
+) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = regex.captures(line) {
+        let rs1 = register(&caps[1])?;
+        let rs2 = register(&caps[2])?;
+        let label: String = caps[3].to_string();
+        Ok(Some(Instruction::Branch {
+            op,
+            rs1,
+            rs2,
+            label,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_beq(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    parse_branch(&BEQ_REGEX, BranchOp::Beq, line)
+}
+
+fn parse_blt(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    parse_branch(&BLT_REGEX, BranchOp::Blt, line)
+}
+
+/// Parse `bne`, `bge`, `bltu` or `bgeu`.
+fn parse_conditional_branch(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = BRANCH_REGEX.captures(line) {
+        let op = match &caps[1] {
+            "bne" => BranchOp::Bne,
+            "bge" => BranchOp::Bge,
+            "bltu" => BranchOp::Bltu,
+            _ => BranchOp::Bgeu,
+        };
+        let rs1 = register(&caps[2])?;
+        let rs2 = register(&caps[3])?;
+        let label = caps[4].to_string();
+        Ok(Some(Instruction::Branch {
+            op,
+            rs1,
+            rs2,
+            label,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parse `bgt`, `ble`, `bgtu` or `bleu`, which are `blt`, `bge`, `bltu` and
+/// `bgeu` with their operands swapped.
+fn parse_swapped_branch(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = SWAPPED_REGEX.captures(line) {
+        let op = match &caps[1] {
+            "bgt" => BranchOp::Blt,
+            "ble" => BranchOp::Bge,
+            "bgtu" => BranchOp::Bltu,
+            _ => BranchOp::Bgeu,
+        };
+        let rs = register(&caps[2])?;
+        let rt = register(&caps[3])?;
+        let label = caps[4].to_string();
+        Ok(Some(Instruction::Branch {
+            op,
+            rs1: rt,
+            rs2: rs,
+            label,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_addi(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = ADDI_REGEX.captures(line) {
+        let rd = register(&caps[1])?;
+        let rs1 = register(&caps[2])?;
+        let imm = if let Some(imm) = caps.get(3) {
+            let imm = parse_immediate(imm.as_str())?;
+            if !(-2048..=2047).contains(&imm) {
+                return Err(AssembleError::InvalidImmediate(caps[3].to_string()));
+            }
+            Immediate::Value(imm as i32)
+        } else if let Some(label) = caps.get(4) {
+            Immediate::Relocation(Relocation::PcrelLo(label.as_str().to_string()))
+        } else {
+            Immediate::Relocation(Relocation::Lo(caps[5].to_string()))
+        };
+        Ok(Some(Instruction::I {
+            op: IOp::Addi,
+            rd,
+            rs1,
+            imm,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_auipc(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = AUIPC_REGEX.captures(line) {
+        let rd = register(&caps[1])?;
+        let imm = if let Some(imm) = caps.get(2) {
+            let imm = parse_immediate(imm.as_str())?;
+            if !(0..=0xfffff).contains(&imm) {
+                return Err(AssembleError::InvalidImmediate(caps[2].to_string()));
+            }
+            Immediate::Value(imm as i32)
+        } else {
+            let label = caps[3].to_string();
+            Immediate::Relocation(Relocation::PcrelHi(label))
+        };
+        Ok(Some(Instruction::U {
+            op: UOp::Auipc,
+            rd,
+            imm,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_lui(line: &str) -> Result<Option<Instruction>, AssembleError> {
+    if let Some(caps) = LUI_REGEX.captures(line) {
+        let rd = register(&caps[1])?;
+        let imm = if let Some(imm) = caps.get(2) {
+            let imm = parse_immediate(imm.as_str())?;
+            if !(0..=0xfffff).contains(&imm) {
+                return Err(AssembleError::InvalidImmediate(caps[2].to_string()));
+            }
+            Immediate::Value(imm as i32)
+        } else {
+            Immediate::Relocation(Relocation::Hi(caps[3].to_string()))
+        };
+        Ok(Some(Instruction::U {
+            op: UOp::Lui,
+            rd,
+            imm,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_li(line: &str) -> Result<Option<Vec<Instruction>>, AssembleError> {
+    if let Some(caps) = LI_REGEX.captures(line) {
+        let rd = register(&caps[1])?;
+        let imm = parse_immediate(&caps[2])?;
+        if !(i32::MIN as i64..=u32::MAX as i64).contains(&imm) {
+            return Err(AssembleError::InvalidImmediate(caps[2].to_string()));
+        }
+        Ok(Some(expand_li(rd, imm as u32 as i32)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parse `la rd, symbol`, which loads the label's absolute address with
+/// `lui` + `addi`, or `lla rd, symbol`, which computes it relative to the
+/// PC with `auipc` + `addi` so the code stays position-independent.
+fn parse_la(line: &str) -> Result<Option<Vec<Instruction>>, AssembleError> {
+    if let Some(caps) = LA_REGEX.captures(line) {
+        let rd = register(&caps[2])?;
+        let label = caps[3].to_string();
+        let (upper, lower) = if &caps[1] == "la" {
+            (
+                Instruction::U {
+                    op: UOp::Lui,
+                    rd,
+                    imm: Immediate::Relocation(Relocation::Hi(label.clone())),
+                },
+                Relocation::Lo(label),
+            )
+        } else {
+            (
+                Instruction::U {
+                    op: UOp::Auipc,
+                    rd,
+                    imm: Immediate::Relocation(Relocation::PcrelHi(label.clone())),
+                },
+                Relocation::PcrelPairLo(label),
+            )
+        };
+        Ok(Some(vec![
+            upper,
+            Instruction::I {
+                op: IOp::Addi,
+                rd,
+                rs1: rd,
+                imm: Immediate::Relocation(lower),
+            },
+        ]))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Encode a GNU `.insn` directive of `format` from its operands, with
+/// branch and jump offsets given in bytes:
+///
+/// ```text
+/// .insn r opcode, funct3, funct7, rd, rs1, rs2
+/// .insn i opcode, funct3, rd, rs1, simm12
+/// .insn s opcode, funct3, rs2, simm12(rs1)
+/// .insn b opcode, funct3, rs1, rs2, offset
+/// .insn u opcode, rd, simm20
+/// .insn j opcode, rd, offset
+/// ```
+fn parse_insn(line: &str, format: &str, operands: &str) -> Result<Instruction, AssembleError> {
+    let operands: Vec<&str> = operands.split(',').map(str::trim).collect();
+    let expected = match format {
+        "r" => 6,
+        "i" | "b" => 5,
+        "s" => 4,
+        _ => 3,
+    };
+    if operands.len() != expected {
+        return Err(AssembleError::InvalidInstruction(line.to_string()));
+    }
+    // An operand that must lie in `range`.
+    let field = |operand: &str, range: std::ops::RangeInclusive<i64>| {
+        let value = parse_immediate(operand)?;
+        if range.contains(&value) {
+            Ok(value)
+        } else {
+            Err(AssembleError::InvalidImmediate(operand.to_string()))
+        }
+    };
+    let opcode = field(operands[0], 0..=0x7f)? as u32;
+    let funct3 = || field(operands[1], 0..=7).map(|funct3| funct3 as u32);
+    let (format, word) = match format {
+        "r" => {
+            let funct7 = field(operands[2], 0..=0x7f)? as u32;
+            let rd = register(operands[3])?;
+            let (rs1, rs2) = (register(operands[4])?, register(operands[5])?);
+            let word = opcode | rd << 7 | funct3()? << 12 | rs1 << 15 | rs2 << 20 | funct7 << 25;
+            ("R", word)
+        }
+        "i" => {
+            let (rd, rs1) = (register(operands[2])?, register(operands[3])?);
+            let imm = field(operands[4], -2048..=2047)? as i32;
+            ("I", build_itype(opcode, funct3()?, rd, rs1, imm))
+        }
+        "s" => {
+            let rs2 = register(operands[2])?;
+            let (imm, rs1) = operands[3]
+                .strip_suffix(')')
+                .and_then(|address| address.split_once('('))
+                .ok_or_else(|| AssembleError::InvalidInstruction(line.to_string()))?;
+            let imm = field(imm.trim(), -2048..=2047)? as i32;
+            (
+                "S",
+                build_stype(opcode, funct3()?, register(rs1.trim())?, rs2, imm),
+            )
+        }
+        "b" => {
+            let (rs1, rs2) = (register(operands[2])?, register(operands[3])?);
+            let offset = field(operands[4], -4096..=4094)? as u32;
+            if !offset.is_multiple_of(2) {
+                return Err(AssembleError::InvalidImmediate(operands[4].to_string()));
+            }
+            let mut word = opcode | funct3()? << 12 | rs1 << 15 | rs2 << 20;
+            word |= ((offset >> 11) & 0b1) << 7;
+            word |= ((offset >> 1) & 0b1111) << 8;
+            word |= ((offset >> 5) & 0b11_1111) << 25;
+            word |= ((offset >> 12) & 0b1) << 31;
+            ("B", word)
+        }
+        "u" => {
+            let rd = register(operands[1])?;
+            let imm = field(operands[2], -0x80000..=0xfffff)? as u32;
+            ("U", opcode | rd << 7 | (imm & 0xfffff) << 12)
+        }
+        _ => {
+            let rd = register(operands[1])?;
+            let offset = field(operands[2], -0x100000..=0xffffe)? as u32;
+            if !offset.is_multiple_of(2) {
+                return Err(AssembleError::InvalidImmediate(operands[2].to_string()));
+            }
+            let mut word = opcode | rd << 7;
+            word |= ((offset >> 12) & 0b1111_1111) << 12;
+            word |= ((offset >> 11) & 0b1) << 20;
+            word |= ((offset >> 1) & 0b11_1111_1111) << 21;
+            word |= ((offset >> 20) & 0b1) << 31;
+            ("J", word)
+        }
+    };
+    Ok(Instruction::Raw { format, word })
+}
+
+/// Expand `li rd, imm` into `addi`, `lui`, or `lui` + `addi`. The `addi`
+/// immediate is sign-extended, so when bit 11 of `imm` is set the upper
+/// 20 bits loaded by `lui` are incremented by one to compensate.
+fn expand_li(rd: u32, imm: i32) -> Vec<Instruction> {
+    let lo = (imm << 20) >> 20;
+    let hi = (imm.wrapping_sub(lo) as u32) >> 12;
+    let addi = |rs1| Instruction::I {
+        op: IOp::Addi,
+        rd,
+        rs1,
+        imm: Immediate::Value(lo),
+    };
+    let lui = Instruction::U {
+        op: UOp::Lui,
+        rd,
+        imm: Immediate::Value(hi as i32),
+    };
+    match (hi, lo) {
+        (0, _) => vec![addi(0)],
+        (_, 0) => vec![lui],
+        _ => vec![lui, addi(rd)],
+    }
+}
+
+/// Patch relocations into `instructions`, with offsets and addresses in
+/// `unit`. Branches already count instructions, so only jumps and the
+/// `auipc`/`lui` pairs depend on the unit; a word-addressed jump counts
+/// words in the field that otherwise counts halfwords. An error is
+/// returned with the index of the instruction it arose at.
+fn transform_labels(
+    instructions: Vec<(u32, Option<Relocation>)>,
+    addresses: &[usize],
+    labels: Labels,
+    unit: AddressUnit,
+) -> Result<Vec<u32>, (usize, AssembleError)> {
+    let mut words = Vec::with_capacity(instructions.len());
+    patch_labels(&instructions, addresses, &labels, unit, |_, word| {
+        words.push(word)
+    })?;
+    Ok(words)
+}
+
+/// Patch every relocated word, handing each word and its index to `emit` in
+/// order.
+fn patch_labels(
+    instructions: &[(u32, Option<Relocation>)],
+    addresses: &[usize],
+    labels: &Labels,
+    unit: AddressUnit,
+    mut emit: impl FnMut(usize, u32),
+) -> Result<(), (usize, AssembleError)> {
+    let resolve = |label: &str, i: usize| {
+        labels
+            .resolve(label, i)
+            .ok_or_else(|| AssembleError::InvalidLabel {
+                label: label.to_string(),
+                suggestion: suggest_label(label, &labels.global),
+            })
+    };
+    // The offset from the instruction at index `i` to `label`.
+    let pcrel = |label: &str, i: usize| -> Result<i64, AssembleError> {
+        Ok((resolve(label, i)?.address as i64 - addresses[i] as i64) / unit.bytes() as i64)
+    };
+    let patch = |i: usize, mut inst: u32, relocation: &Option<Relocation>| {
+        match relocation {
+            Some(Relocation::Branch(label)) => {
+                let (i, j) = (addresses[i] / 4, resolve(label, i)?.address / 4);
+                if i.max(j) - i.min(j) > MAX_BRANCH_DISTANCE {
+                    return Err(AssembleError::BranchOutOfRange {
+                        label: label.to_string(),
+                        distance: j as isize - i as isize,
+                    });
+                }
+                // In range, so the distance fits the 5-bit field.
+                let imm = (i.max(j) - i.min(j)) as u32;
+                inst |= (imm & 0b00000000_00000000_00000000_00011111) << 7;
+                if i >= j {
+                    inst |= 0b00000010_00000000_00000000_00000000;
+                }
+            }
+            Some(Relocation::Jump(label)) => {
+                let offset = match unit {
+                    AddressUnit::Byte => pcrel(label, i)?,
+                    AddressUnit::Word => pcrel(label, i)? * 2,
+                };
+                if !(-0x100000..0x100000).contains(&offset) {
+                    return Err(AssembleError::BranchOutOfRange {
+                        label: label.to_string(),
+                        distance: offset as isize / 4,
+                    });
+                }
+                let offset = offset as u32;
+                inst |= ((offset >> 20) & 0b1) << 31;
+                inst |= ((offset >> 1) & 0b11_1111_1111) << 21;
+                inst |= ((offset >> 11) & 0b1) << 20;
+                inst |= ((offset >> 12) & 0b1111_1111) << 12;
+            }
+            Some(Relocation::PcrelHi(label)) => {
+                let offset = pcrel(label, i)?;
+                inst |= (((offset + 0x800) >> 12) as u32) << 12;
+            }
+            Some(Relocation::PcrelLo(label)) => {
+                let j = resolve(label, i)?.index;
+                match instructions.get(j) {
+                    Some((_, Some(Relocation::PcrelHi(target)))) => {
+                        let offset = pcrel(target, j)?;
+                        inst |= ((offset & 0xfff) as u32) << 20;
+                    }
+                    _ => return Err(AssembleError::InvalidRelocation(label.to_string())),
+                }
+            }
+            Some(Relocation::PcrelPairLo(label)) => {
+                let offset = pcrel(label, i - 1)?;
+                inst |= ((offset & 0xfff) as u32) << 20;
+            }
+            Some(Relocation::Hi(label)) => {
+                let address = unit.scale(resolve(label, i)?.address) as u32;
+                inst |= (address.wrapping_add(0x800) >> 12) << 12;
+            }
+            Some(Relocation::Lo(label)) => {
+                let address = unit.scale(resolve(label, i)?.address) as u32;
+                // Stores split their offset around `rs1` and `rs2`.
+                if matches!(inst & 0b1111111, 0b0100011 | 0b0100111) {
+                    inst |= (address & 0b11111) << 7 | ((address >> 5) & 0b1111111) << 25;
+                } else {
+                    inst |= (address & 0xfff) << 20;
+                }
+            }
+            None => {}
+        }
+        Ok(inst)
+    };
+    for (i, (inst, relocation)) in instructions.iter().enumerate() {
+        emit(i, patch(i, *inst, relocation).map_err(|error| (i, error))?);
+    }
+    Ok(())
+}
+
+/// The known label closest to `label` by edit distance, if any is close
+/// enough to plausibly be a typo.
+fn suggest_label<T>(label: &str, labels: &HashMap<String, T>) -> Option<String> {
+    labels
+        .keys()
+        .map(|known| (edit_distance(label, known), known))
+        .filter(|(distance, _)| *distance <= label.chars().count() / 3 + 1)
+        .min()
+        .map(|(_, known)| known.to_string())
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)]
+mod tests {
+    use super::*;
+
+    /// Known-good encodings as reported by `llvm-mc -triple=riscv64 -show-encoding`.
+    const ENCODINGS: &[(&str, u32)] = &[
+        ("ld x0, 0(x0)", 0x00003003),
+        ("ld x5, 40(x6)", 0x02833283),
+        ("ld x31, 2047(x31)", 0x7fffbf83),
+        ("sd x0, 0(x0)", 0x00003023),
+        ("sd x5, 40(x6)", 0x02533423),
+        ("sd x31, 31(x1)", 0x01f0bfa3),
+        ("sd x1, 32(x31)", 0x021fb023),
+        ("sd x31, 2047(x31)", 0x7fffbfa3),
+        ("and x5, x6, x7", 0x007372b3),
+        ("and x31, x31, x31", 0x01ffffb3),
+        ("or x5, x6, x7", 0x007362b3),
+        ("or x31, x0, x31", 0x01f06fb3),
+        ("add x5, x6, x7", 0x007302b3),
+        ("add x0, x0, x0", 0x00000033),
+        ("add x31, x31, x31", 0x01ff8fb3),
+        ("sub x5, x6, x7", 0x407302b3),
+        ("sub x31, x31, x31", 0x41ff8fb3),
+        ("addi x5, x6, -1", 0xfff30293),
+        ("addi x31, x0, 2047", 0x7ff00f93),
+        ("addi x5, x6, -2048", 0x80030293),
+        ("auipc x5, 0x12345", 0x12345297),
+        ("auipc x31, 0xfffff", 0xffffff97),
+        ("lui x5, 0x12345", 0x123452b7),
+        ("slt x5, x6, x7", 0x007322b3),
+        ("sltu x5, x6, x7", 0x007332b3),
+        ("mul x5, x6, x7", 0x027302b3),
+        ("mulh x5, x6, x7", 0x027312b3),
+        ("mulhsu x5, x6, x7", 0x027322b3),
+        ("mulhu x5, x6, x7", 0x027332b3),
+        ("div x5, x6, x7", 0x027342b3),
+        ("divu x5, x6, x7", 0x027352b3),
+        ("rem x5, x6, x7", 0x027362b3),
+        ("remu x31, x30, x0", 0x020f7fb3),
+        ("ecall", 0x00000073),
+        ("ebreak", 0x00100073),
+        ("wfi", 0x10500073),
+        ("mret", 0x30200073),
+        ("sret // return to S-mode", 0x10200073),
+        ("sfence.vma", 0x12000073),
+        ("sfence.vma x5, x6", 0x12628073),
+        ("sfence.vma x5", 0x12028073),
+    ];
+
+    /// Known-good F-extension encodings, from `llvm-mc -mattr=+f`.
+    const F_ENCODINGS: &[(&str, u32)] = &[
+        ("flw f1, 0(x2)", 0x00012087),
+        ("flw ft0, 2047(sp)", 0x7ff12007),
+        ("fsw f1, 8(x2)", 0x00112427),
+        ("fadd.s f1, f2, f3", 0x003170d3),
+        ("fadd.s f1, f2, f3, rne", 0x003100d3),
+        ("fsub.s f4, f5, f6, rtz", 0x08629253),
+        ("fmul.s f7, f8, f9, rdn", 0x109423d3),
+        ("fdiv.s f31, f30, f29, rup", 0x19df3fd3),
+        ("fadd.s fa0, fa1, ft0, rmm", 0x0005c553),
+    ];
+
+    /// Encodings specific to the risc-v-processor, which uses an all-zero `nop`
+    /// and word-granular, sign-magnitude branch offsets.
+    const PROCESSOR_ENCODINGS: &[(&str, u32)] = &[
+        ("nop", 0x00000000),
+        ("beq x5, x6, Label\nnop\nLabel:", 0x00628163),
+        ("beq x31, x31, Label\n.org 0x7c\nLabel:", 0x01ff8fe3),
+        ("blt x6, x7, Label\nLabel:", 0x007340e3),
+        ("Label:\nblt x5, x6, Label", 0x0262c063),
+        ("bne x6, x7, Label\nLabel:", 0x007310e3),
+        ("bge x6, x7, Label\nLabel:", 0x007350e3),
+        ("bltu x6, x7, Label\nLabel:", 0x007360e3),
+        ("bgeu x6, x7, Label\nLabel:", 0x007370e3),
+    ];
+
+    /// Assemble `asm` and compare its first word against `expected`. Lines after
+    /// the first instruction only provide context such as branch targets.
+    fn assert_encoding(asm: &str, expected: u32) {
+        let instructions = assemble(asm).unwrap();
+        assert_eq!(
+            instructions[0], expected,
+            "`{}` encoded as {:#010x}, expected {:#010x}",
+            asm, instructions[0], expected
+        );
+    }
+
+    #[test]
+    fn encodings() {
+        for (asm, expected) in ENCODINGS
+            .iter()
+            .chain(PROCESSOR_ENCODINGS)
+            .chain(F_ENCODINGS)
+        {
+            assert_encoding(asm, *expected);
+        }
+    }
+
+    #[test]
+    fn ld() {
+        let instruction = parse_load("ld x25, 40(x6)").unwrap().unwrap().encode();
+        assert_eq!(instruction, 0b000000101000_00110_011_11001_0000011);
+    }
+
+    #[test]
+    fn sd() {
+        let instruction = parse_sd("sd x5, 40(x6)").unwrap().unwrap().encode();
+        assert_eq!(instruction, 0b0000001_00101_00110_011_01000_0100011);
+    }
+
+    #[test]
+    fn store_operand_order() {
+        assert_eq!(
+            parse_sd("sd x5, 8(x6)"),
+            Ok(Some(Instruction::Store {
+                op: StoreOp::Sd,
+                rs1: 6,
+                rs2: 5,
+                imm: Immediate::Value(8),
+            }))
+        );
+        for line in ["sd x5, 8(x6)", "fsw f5, 8(x6)"] {
+            let word = assemble_one(line).unwrap()[0];
+            assert_eq!((word >> 15) & 0b11111, 6, "rs1 of {}", line);
+            assert_eq!((word >> 20) & 0b11111, 5, "rs2 of {}", line);
+        }
+        assert_ne!(assemble_one("sd x5, 8(x6)"), assemble_one("sd x6, 8(x5)"));
+    }
+
+    #[test]
+    fn and() {
+        let instruction = parse_and("and x5, x6, x7").unwrap().unwrap().encode();
+        assert_eq!(instruction, 0b0000000_00111_00110_111_00101_0110011);
+    }
+
+    #[test]
+    fn or() {
+        let instruction = parse_or("or x5, x6, x7").unwrap().unwrap().encode();
+        assert_eq!(instruction, 0b0000000_00111_00110_110_00101_0110011);
+    }
+
+    #[test]
+    fn add() {
+        let instruction = parse_add("add x5, x6, x7").unwrap().unwrap().encode();
+        assert_eq!(instruction, 0b0000000_00111_00110_000_00101_0110011);
+    }
+
+    #[test]
+    fn sub() {
+        let instruction = parse_sub("sub x5, x6, x7").unwrap().unwrap().encode();
+        assert_eq!(instruction, 0b0100000_00111_00110_000_00101_0110011);
+    }
+
+    #[test]
+    fn swapped_branches() {
+        for (pseudo, base) in [
+            ("bgt", "blt"),
+            ("ble", "bge"),
+            ("bgtu", "bltu"),
+            ("bleu", "bgeu"),
+        ] {
+            let forward = format!("{} x5, x6, L\nnop\nL:", pseudo);
+            let swapped = format!("{} x6, x5, L\nnop\nL:", base);
+            assert_eq!(assemble(&forward).unwrap(), assemble(&swapped).unwrap());
+            let backward = format!("L:\nnop\n{} x5, x6, L", pseudo);
+            let swapped = format!("L:\nnop\n{} x6, x5, L", base);
+            assert_eq!(assemble(&backward).unwrap(), assemble(&swapped).unwrap());
+        }
+        let options = Options {
+            relax_branches: true,
+            ..Options::default()
+        };
+        let far = "bgt x5, x6, Far\n.org 0x100\nFar:\nnop";
+        let swapped = "blt x6, x5, Far\n.org 0x100\nFar:\nnop";
+        assert_eq!(
+            assemble_program_with(far, &options).unwrap().instructions,
+            assemble_program_with(swapped, &options)
+                .unwrap()
+                .instructions
+        );
+    }
+
+    #[test]
+    fn beq() {
+        let inst = parse_beq("beq x5, x6, Label").unwrap().unwrap();
+        let instructions = vec![(inst.encode(), inst.relocation())];
+        let mut labels = Labels::default();
+        let position = Position {
+            index: 2,
+            address: 8,
+        };
+        labels.insert("Label".to_string(), position);
+        let instructions = transform_labels(instructions, &[0], labels, AddressUnit::Byte).unwrap();
+        assert_eq!(instructions[0], 0b0000000_00110_00101_000_00010_1100011);
+    }
+
+    #[test]
+    fn blt() {
+        let inst = parse_blt("blt x5, x6, Label").unwrap().unwrap();
+        let instructions = vec![(0, None), (0, None), (inst.encode(), inst.relocation())];
+        let mut labels = Labels::default();
+        let position = Position {
+            index: 0,
+            address: 0,
+        };
+        labels.insert("Label".to_string(), position);
+        let instructions =
+            transform_labels(instructions, &[0, 4, 8], labels, AddressUnit::Byte).unwrap();
+        assert_eq!(instructions[2], 0b0000001_00110_00101_100_00010_1100011);
+    }
+
+    #[test]
+    fn whitespace() {
+        let spaces = " ".repeat(10_000);
+        let line = format!("ld{0}x5{0},{0}40{0}({0}x6{0}){0}// comment", spaces);
+        let start = std::time::Instant::now();
+        assert_eq!(parse_load(&line), parse_load("ld x5, 40(x6)"));
+        let line = format!("add{0}x5{0},{0}x6{0},{0}", spaces);
+        assert_eq!(parse_add(&line), Ok(None));
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn org_hex() {
+        let instructions = assemble("beq x5, x6, Label\n.org 0x10\nLabel:\nnop").unwrap();
+        assert_eq!(instructions.len(), 5);
+        assert_eq!(instructions[0], 0b0000000_00110_00101_000_00100_1100011);
+        assert_eq!(instructions[1..], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn org_backwards() {
+        let err = assemble("nop\nnop\nnop\n.org 0x4").unwrap_err();
+        assert_eq!(
+            err,
+            AssembleError::BackwardOrigin {
+                current: 0xc,
+                requested: 0x4
+            }
+        );
+        assert!(err
+            .to_string()
+            .contains("current offset 0xc, requested offset 0x4"));
+    }
+
+    #[test]
+    fn skip() {
+        let program = assemble_program("First:\nnop\n.skip 16\nSecond:\nnop").unwrap();
+        assert_eq!(program.symbols["First"].address, 0);
+        assert_eq!(program.symbols["Second"].address, 20);
+        assert_eq!(program.instructions, [0, 0, 0, 0, 0, 0]);
+        assert_eq!(assemble(".space 0x8"), Ok(vec![0, 0]));
+        assert_eq!(assemble(".skip 6"), Err(AssembleError::UnalignedSkip(6)));
+        // Large padding is laid out in one step, and absurd padding fails.
+        let program = assemble_program("nop\n.org 0x400000\nEnd:\nnop").unwrap();
+        assert_eq!(program.symbols["End"].address, 0x400000);
+        assert_eq!(program.instructions.len(), 0x100001);
+        assert_eq!(
+            assemble(".org 0x10000000"),
+            Err(AssembleError::PaddingTooLarge(0x10000000))
+        );
+        assert_eq!(
+            assemble("nop\n.skip 0x10000000"),
+            Err(AssembleError::PaddingTooLarge(0x10000000))
+        );
+    }
+
+    #[test]
+    fn conditionals() {
+        let asm = ".ifdef DEBUG\n\
+                   add x5, x6, x7\n\
+                   .ifndef VERBOSE\n\
+                   sub x5, x6, x7\n\
+                   .endif\n\
+                   .else\n\
+                   or x5, x6, x7\n\
+                   .endif\n\
+                   .ifndef DEBUG\n\
+                   and x5, x6, x7\n\
+                   .endif";
+        let mut defines = HashMap::new();
+        defines.insert("DEBUG".to_string(), 1);
+        let options = Options {
+            defines,
+            ..Options::default()
+        };
+        let program = assemble_program_with(asm, &options).unwrap();
+        assert_eq!(
+            program.instructions,
+            [
+                assemble_one("add x5, x6, x7").unwrap()[0],
+                assemble_one("sub x5, x6, x7").unwrap()[0]
+            ]
+        );
+        assert_eq!(assemble(asm), assemble("or x5, x6, x7\nand x5, x6, x7"));
+        assert_eq!(
+            assemble(&format!(".equ DEBUG, 0\n{}", asm)),
+            Ok(program.instructions)
+        );
+        assert_eq!(
+            assemble(".ifdef DEBUG\nnop"),
+            Err(AssembleError::UnbalancedConditional(".ifdef".to_string()))
+        );
+        assert_eq!(
+            assemble("nop\n.endif"),
+            Err(AssembleError::UnbalancedConditional(".endif".to_string()))
+        );
+    }
+
+    #[test]
+    fn relax_branches() {
+        let asm = "beq x5, x6, Far\nblt x5, x6, Far\n.org 0x100\nFar:\nnop";
+        assert_eq!(
+            assemble(asm),
+            Err(AssembleError::BranchOutOfRange {
+                label: "Far".to_string(),
+                distance: 64
+            })
+        );
+        let options = Options {
+            relax_branches: true,
+            ..Options::default()
+        };
+        let program = assemble_program_with(asm, &options).unwrap();
+        // bne x5, x6, +2 and bge x5, x6, +2 skip over `jal x0, Far`, whose
+        // expected words come from `llvm-mc -triple=riscv64 -show-encoding`.
+        assert_eq!(
+            program.instructions[0],
+            0b0000000_00110_00101_001_00010_1100011
+        );
+        assert_eq!(program.instructions[1], 0x0fc0006f);
+        assert_eq!(
+            program.instructions[2],
+            0b0000000_00110_00101_101_00010_1100011
+        );
+        assert_eq!(program.instructions[3], 0x0f40006f);
+        assert_eq!(program.symbols["Far"].address, 0x100);
+        let near = "beq x5, x6, Near\nNear:";
+        assert_eq!(
+            assemble_program_with(near, &options).unwrap().instructions,
+            assemble(near).unwrap()
+        );
+    }
+
+    #[test]
+    fn next_instruction_branches() {
+        let options = Options {
+            warn_zero_branches: true,
+            ..Options::default()
+        };
+        let asm = "nop\nbeq x5, x6, Next\nNext:\nj After\nAfter:\nnop";
+        let program = assemble_program_with(asm, &options).unwrap();
+        assert_eq!(
+            program.warnings,
+            [
+                "the branch to `Next` at 0x4 goes to the next instruction",
+                "the branch to `After` at 0x8 goes to the next instruction",
+            ]
+        );
+        let program = assemble_program_with("Loop:\nnop\nbeq x5, x6, Loop", &options).unwrap();
+        assert!(program.warnings.is_empty());
+        let program = assemble_program_with("Halt:\nj Halt", &options).unwrap();
+        assert!(program.warnings.is_empty());
+        let program = assemble_program("beq x5, x6, Next\nNext:\nnop").unwrap();
+        assert!(program.warnings.is_empty());
+    }
+
+    #[test]
+    fn xref() {
+        let asm = "Loop:\nbeq x5, x6, Exit\nbne x5, x0, Loop\nj Loop\n1:\nj 1b\nExit:";
+        let references = cross_references(asm, &Options::default()).unwrap();
+        assert_eq!(
+            references.into_iter().collect::<Vec<_>>(),
+            [
+                ("Exit".to_string(), vec![0]),
+                ("Loop".to_string(), vec![4, 8])
+            ]
+        );
+    }
+
+    #[test]
+    fn unused_labels() {
+        let options = Options {
+            warn_unused_labels: true,
+            ..Options::default()
+        };
+        let asm = ".globl Start\nStart:\nLoop:\nbne x5, x0, Loop\nDead:\nnop";
+        let program = assemble_program_with(asm, &options).unwrap();
+        assert_eq!(program.warnings, ["the label `Dead` is never referenced"]);
+        assert!(assemble_program(asm).unwrap().warnings.is_empty());
+    }
+
+    #[test]
+    fn null_stores() {
+        let options = Options {
+            warn_null_stores: Some(4096),
+            ..Options::default()
+        };
+        let program = assemble_program_with("sd x1, 8(x0)", &options).unwrap();
+        assert_eq!(
+            program.warnings,
+            ["`sd` stores to 0x8 from `x0`, which is likely a null pointer"]
+        );
+        let options = Options {
+            warn_null_stores: Some(8),
+            ..options
+        };
+        for asm in [
+            "sd x1, 8(x2)",
+            "sd x1, 0(x0)",
+            "sd x1, 8(x0)",
+            "ld x5, 4(x0)",
+        ] {
+            let program = assemble_program_with(asm, &options).unwrap();
+            assert!(program.warnings.is_empty(), "{}", asm);
+        }
+        assert!(assemble_program("sd x1, 8(x0)")
+            .unwrap()
+            .warnings
+            .is_empty());
+    }
+
+    #[test]
+    fn jal() {
+        let asm = |jal: &str| assemble(&format!("{}\nnop\nEnd:", jal));
+        assert_eq!(asm("jal x1, End"), Ok(vec![0x008000ef, 0]));
+        assert_eq!(asm("jal End"), asm("jal x1, End"));
+        assert_eq!(asm("jal ra, End // link"), asm("jal x1, End"));
+        assert_eq!(asm("jal x0, End"), asm("j End"));
+        assert!(assemble_program("jal x0, End\nEnd:")
+            .unwrap()
+            .warnings
+            .is_empty());
+        assert!(asm("jal x32, End").is_err());
+    }
+
+    #[test]
+    fn x0_destination() {
+        let options = Options {
+            warn_x0_writes: true,
+            ..Options::default()
+        };
+        let program = assemble_program_with("Loop:\nj Loop\nli x0, 1", &options).unwrap();
+        assert_eq!(program.instructions[0], 0x0000006f);
+        assert!(program.warnings.is_empty());
+        let program = assemble_program_with("addi x0, x1, 5", &options).unwrap();
+        assert_eq!(
+            program.warnings,
+            ["`addi` writes to `x0`, so its result is discarded"]
+        );
+        let program =
+            assemble_program_with("addi x0, x0, 0\naddi zero, zero, 0", &options).unwrap();
+        assert!(program.warnings.is_empty());
+        let program = assemble_program_with("add x0, x1, x2\naddi x0, x0, 1", &options).unwrap();
+        assert_eq!(program.warnings.len(), 2);
+        // Silent unless enabled.
+        let program = assemble_program("add x0, x1, x2\naddi x0, x1, 5").unwrap();
+        assert!(program.warnings.is_empty());
+    }
+
+    #[test]
+    fn call() {
+        let asm = "call foo\ncall foo@plt\ntail foo@plt\nfoo:";
+        let instructions = assemble(asm).unwrap();
+        // Expected words from `llvm-mc -triple=riscv64 -show-encoding` for
+        // `jal ra, 12`, `jal ra, 8` and `jal x0, 4`.
+        assert_eq!(instructions, [0x00c000ef, 0x008000ef, 0x0040006f]);
+        let err = assemble("call printf@plt").unwrap_err();
+        assert!(matches!(err, AssembleError::InvalidLabel { .. }));
+    }
+
+    #[test]
+    fn offset_format() {
+        let err = assemble("Back:\n.skip 0x100\nbeq x5, x6, Back").unwrap_err();
+        assert_eq!(
+            err.display(OffsetFormat::Decimal).to_string(),
+            "Invalid Branch: `Back` is -64 instructions away"
+        );
+        assert_eq!(
+            err.display(OffsetFormat::Hex).to_string(),
+            "Invalid Branch: `Back` is -0x40 instructions away"
+        );
+        assert_eq!(
+            err.to_string(),
+            err.display(OffsetFormat::Decimal).to_string()
+        );
+    }
+
+    #[test]
+    fn divide_by_x0() {
+        let program = assemble_program("div x5, x6, x0\nremu x5, x6, zero").unwrap();
+        assert_eq!(
+            program.warnings,
+            [
+                "`div` divides by `x0`, which always gives the divide-by-zero result",
+                "`remu` divides by `x0`, which always gives the divide-by-zero result",
+            ]
+        );
+        assert!(assemble_program("div x5, x6, x7\nmul x5, x6, x0")
+            .unwrap()
+            .warnings
+            .is_empty());
+    }
+
+    #[test]
+    fn line_map() {
+        let asm = "Main:\naddi x5, x0, 1\nli x6, 0x12345\n\nadd x7, x5, x6\n.data\n.ascii \"hi\"";
+        let options = Options {
+            data_base: 0x1000,
+            ..Options::default()
+        };
+        assert_eq!(
+            line_addresses(asm, &options),
+            Ok(vec![(2, 0), (3, 4), (5, 12), (7, 0x1000)])
+        );
+    }
+
+    #[test]
+    fn listing_expansion() {
+        let asm = "// setup\nMain:\n    li x5, 0x12345\n    add x5, x6, x7";
+        let listing = listing(asm, &Options::default()).unwrap();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "   1                    // setup",
+                "   2 00000000           Main:",
+                "   3 00000000               li x5, 0x12345",
+                "     00000000 000122b7      lui x5, 0x12",
+                "     00000004 34528293      addi x5, x5, 837",
+                "   4 00000008 007302b3      add x5, x6, x7",
+            ]
+        );
+    }
+
+    #[test]
+    fn listing_data() {
+        let asm = "nop\n.data\n.word 0x12345678, 0x9abcdef0\n.byte 1, 2\n.byte 3";
+        let listing = listing(asm, &Options::default()).unwrap();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "   1 00000000 00000000  nop",
+                "   2 00000004           .data",
+                "   3 00000000 12345678  .word 0x12345678, 0x9abcdef0",
+                "     00000004 9abcdef0",
+                "   4 00000008 00030201  .byte 1, 2",
+                "   5 0000000a           .byte 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn macros() {
+        let asm = ".macro push reg, offset\n\
+                   addi x2, x2, -8\n\
+                   sd \\reg, \\offset(x2)\n\
+                   .endm\n\
+                   push x5, 0\n\
+                   push ra, 4";
+        let expected = "addi x2, x2, -8\nsd x5, 0(x2)\naddi x2, x2, -8\nsd x1, 4(x2)";
+        assert_eq!(assemble(asm), assemble(expected));
+        let asm = ".macro wait\nwait\\@:\nbeq x0, x0, wait\\@\n.endm\nwait\nwait";
+        let program = assemble_program(asm).unwrap();
+        assert_eq!(
+            program.symbols.keys().collect::<Vec<_>>(),
+            ["wait1", "wait2"]
+        );
+        assert_eq!(
+            assemble(".macro loop\nloop\n.endm\nloop"),
+            Err(AssembleError::InvalidMacro(
+                "expansion deeper than 64 levels".to_string()
+            ))
+        );
+        assert!(assemble(".macro push reg\n.endm\npush x5, x6").is_err());
+        assert!(assemble(".macro push reg\nnop").is_err());
+    }
+
+    #[test]
+    fn rept() {
+        let asm = "beq x5, x6, End\n.rept 3\nnop\n.endr\nEnd:\nnop";
+        assert_eq!(
+            assemble(asm),
+            Ok(vec![0b0000000_00110_00101_000_00100_1100011, 0, 0, 0, 0])
+        );
+        let nested = ".rept 2\n.rept 2\nnop\n.endr\naddi x5, x5, 1\n.endr";
+        let expected = "nop\nnop\naddi x5, x5, 1\nnop\nnop\naddi x5, x5, 1";
+        assert_eq!(assemble(nested), assemble(expected));
+        let program = assemble_program(".rept 2\nL\\@:\nnop\n.endr").unwrap();
+        assert_eq!(program.symbols.keys().collect::<Vec<_>>(), ["L1", "L2"]);
+        let local = ".rept 2\n1:\nbne x5, x0, 1b\n.endr";
+        let expected = "1:\nbne x5, x0, 1b\n1:\nbne x5, x0, 1b";
+        assert_eq!(assemble(local), assemble(expected));
+        assert!(assemble(".rept 2\nnop").is_err());
+        assert!(assemble(".endr").is_err());
+    }
+
+    #[test]
+    fn locations() {
+        let asm = "nop\n.ifdef DEBUG\n.error \"debug builds are unsupported\"\n.endif";
+        let mut options = Options::default();
+        assert!(assemble_program_located(asm, &options).is_ok());
+        options.defines.insert("DEBUG".to_string(), 1);
+        let line = |err: LocatedError| err.span.map(|span| span.line);
+        let err = assemble_program_located(asm, &options).unwrap_err();
+        assert_eq!(err.error.to_string(), "debug builds are unsupported");
+        assert_eq!(line(err), Some(2));
+        let err = assemble_program_located("nop\n\nbeq x5, x6, Exit", &options).unwrap_err();
+        assert_eq!(line(err), Some(2));
+        let err = assemble_program_located(".org 0x8\n.org 0x4", &options).unwrap_err();
+        assert_eq!(line(err), Some(1));
+    }
+
+    #[test]
+    fn branch_out_of_range_span() {
+        let asm = format!("nop\n    beq x5, x6, Far\n{}Far:\nnop", "nop\n".repeat(40));
+        let err = assemble_program_located(&asm, &Options::default()).unwrap_err();
+        assert!(matches!(err.error, AssembleError::BranchOutOfRange { .. }));
+        assert_eq!(err.span, Some(Span { line: 1, col: 4 }));
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(assemble_one("sltz x5, x6"), assemble_one("slt x5, x6, x0"));
+        assert_eq!(assemble_one("sgtz x5, x6"), assemble_one("slt x5, x0, x6"));
+        assert_eq!(
+            assemble_one("sgt x5, x6, x7"),
+            assemble_one("slt x5, x7, x6")
+        );
+        assert_eq!(
+            assemble_one("sgtu x5, x6, x7"),
+            assemble_one("sltu x5, x7, x6")
+        );
+    }
+
+    #[test]
+    fn one() {
+        assert_eq!(
+            assemble_one("add x5, x6, x7"),
+            Ok(vec![0b0000000_00111_00110_000_00101_0110011])
+        );
+        assert_eq!(
+            assemble_one("beq x5, x6, L"),
+            Err(AssembleError::UnresolvedLabel("L".to_string()))
+        );
+    }
+
+    #[test]
+    fn instruction_lines() {
+        assert_eq!(
+            parse_instruction("add x5, x6, x7"),
+            Ok(Some(Parsed::Instruction(Instruction::R {
+                op: ROp::Add,
+                rd: 5,
+                rs1: 6,
+                rs2: 7
+            })))
+        );
+        assert_eq!(parse_instruction("frob x5, x6"), Ok(None));
+        assert_eq!(parse_instruction("Loop:"), Ok(None));
+        assert_eq!(
+            parse_instruction("add x5, x6"),
+            Err(AssembleError::InvalidInstruction("add x5, x6".to_string()))
+        );
+        assert_eq!(
+            parse_instruction("ld x5, 8(x6"),
+            Err(AssembleError::UnbalancedParentheses("ld".to_string()))
+        );
+    }
+
+    #[test]
+    fn sections() {
+        let asm = ".text\nStart:\n1:\nauipc x5, %pcrel_hi(Value)\naddi x5, x5, %pcrel_lo(1b)\n\
+                   .data\n.org 8\nValue:\nnop\n\
+                   .text\nj Start";
+        let options = Options {
+            text_base: 0x1000,
+            data_base: 0x2000,
+            ..Options::default()
+        };
+        let program = assemble_program_with(asm, &options).unwrap();
+        // Expected words from `llvm-mc -triple=riscv64 -show-encoding`.
+        assert_eq!(program.instructions, [0x00001297, 0x00828293, 0xff9ff06f]);
+        assert_eq!(program.data, [0, 0, 0]);
+        assert_eq!(program.symbols["Start"].address, 0x1000);
+        assert_eq!(program.symbols["Value"].address, 0x2008);
+        let err = assemble(".data\nnop\n.text\n.org 4\nnop\n.data\n.org 0").unwrap_err();
+        assert_eq!(
+            err,
+            AssembleError::BackwardOrigin {
+                current: 4,
+                requested: 0
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_directives() {
+        assert_eq!(
+            assemble(".foo 1, 2\nnop"),
+            Err(AssembleError::UnknownDirective(".foo".to_string()))
+        );
+        assert_eq!(
+            assemble(".org"),
+            Err(AssembleError::InvalidInstruction(".org".to_string()))
+        );
+        let options = Options {
+            ignore_unknown_directives: true,
+            ..Options::default()
+        };
+        let program = assemble_program_with(".section .rodata\nnop", &options).unwrap();
+        assert_eq!(program.instructions, [0]);
+        assert_eq!(program.warnings, ["ignoring unknown directive `.section`"]);
+    }
+
+    #[test]
+    fn seed_labels() {
+        let mut options = Options::default();
+        options.seed_labels.insert("UART".to_string(), 0x1000_0800);
+        let asm = "lui x5, %hi(UART)\naddi x5, x5, %lo(UART)";
+        let program = assemble_program_with(asm, &options).unwrap();
+        // Expected words from `llvm-mc -triple=riscv64 -show-encoding` for
+        // `lui t0, 0x10001` and `addi t0, t0, -2048`.
+        assert_eq!(program.instructions, [0x100012b7, 0x80028293]);
+        assert_eq!(program.symbols["UART"].address, 0x1000_0800);
+        assert_eq!(
+            assemble_program_with("UART:\nnop", &options),
+            Err(AssembleError::DuplicateLabel("UART".to_string()))
+        );
+        let program = assemble_program("lui x5, %hi(Data)\naddi x5, x5, %lo(Data)\nData:").unwrap();
+        assert_eq!(program.instructions, [0x000002b7, 0x00828293]);
+    }
+
+    #[test]
+    fn reserved_labels() {
+        for label in ["add", "sp", "x5", "fp", "bgt"] {
+            let asm = format!("{}:\nnop", label);
+            assert_eq!(
+                assemble(&asm),
+                Err(AssembleError::ReservedLabel(label.to_string()))
+            );
+        }
+        assert!(assemble("loop:\nbeq x5, x6, loop").is_ok());
+        assert!(assemble("x32:\nadd_one:\nnop").is_ok());
+    }
+
+    #[test]
+    fn label_suggestion() {
+        let err = assemble("Label:\nLoop:\nbeq x5, x6, Lable").unwrap_err();
+        assert_eq!(
+            err,
+            AssembleError::InvalidLabel {
+                label: "Lable".to_string(),
+                suggestion: Some("Label".to_string())
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Invalid Label: `Lable` (did you mean `Label`?)"
+        );
+        let err = assemble("Label:\nbeq x5, x6, Exit").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid Label: `Exit`");
+    }
+
+    #[test]
+    fn pcrel() {
+        let asm = "1:\nauipc x5, %pcrel_hi(Data)\naddi x5, x5, %pcrel_lo(1b)\n\
+                   1:\nauipc x6, %pcrel_hi(Data)\naddi x6, x6, %pcrel_lo(1b)\n\
+                   .org 0x1900\nData:\nnop";
+        let instructions = assemble(asm).unwrap();
+        for i in [0, 2] {
+            let (auipc, addi) = (instructions[i], instructions[i + 1]);
+            assert_eq!(auipc & 0b1111111, 0b0010111);
+            assert_eq!(addi & 0b1111111, 0b0010011);
+            assert_eq!((auipc >> 7) & 0b11111, (addi >> 15) & 0b11111);
+            let pc = i as i64 * 4;
+            let hi = (auipc & 0xfffff000) as i32 as i64;
+            let lo = (addi as i32 >> 20) as i64;
+            assert_eq!(pc + hi + lo, 0x1900);
+        }
+        assert_eq!(instructions[1] >> 20, 0x900);
+        assert_eq!(
+            assemble("Data:\n1:\nnop\naddi x5, x5, %pcrel_lo(1b)").unwrap_err(),
+            AssembleError::InvalidRelocation("1b".to_string())
+        );
+    }
+
+    #[test]
+    fn lo_loads() {
+        let data = ".data\n.org 0x1234\nData:\nnop";
+        let options = Options {
+            data_base: 0x10000,
+            ..Options::default()
+        };
+        let assemble = |asm: &str| {
+            assemble_program_with(&format!("{}\n{}", asm, data), &options).map(|p| p.instructions)
+        };
+        // `Data` is at 0x11234, so its low 12 bits are 0x234.
+        assert_eq!(
+            assemble("lw x5, %lo(Data)(x6)"),
+            Ok(vec![0x234 << 20 | 0b00110_010_00101_0000011])
+        );
+        assert_eq!(
+            assemble("lw x5, Data"),
+            assemble("lui x5, 0x11\nlw x5, 564(x5)")
+        );
+        assert_eq!(assemble("ld x5, %lo(Data)(x6)"), assemble("ld x5, 564(x6)"));
+    }
+
+    #[test]
+    fn offset_range() {
+        for mnemonic in ["ld", "lw", "sd"] {
+            for imm in ["-2048", "2047"] {
+                let line = format!("{} x5, {}(x6)", mnemonic, imm);
+                assert!(assemble_one(&line).is_ok(), "{}", line);
+            }
+            for imm in ["-2049", "2048", "4096", "99999999999"] {
+                let line = format!("{} x5, {}(x6)", mnemonic, imm);
+                assert_eq!(
+                    assemble_one(&line),
+                    Err(AssembleError::InvalidImmediate(imm.to_string())),
+                    "{}",
+                    line
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn offset_expressions() {
+        let asm = ".equ SIZE, 4\nld x5, (SIZE*8)(x6)\nsd x5, (SIZE * (2 + 1) - 1)(x2)";
+        assert_eq!(assemble(asm), assemble("ld x5, 32(x6)\nsd x5, 11(x2)"));
+        assert_eq!(
+            assemble("lw x5, (1 << 4 | 0x3)(x6)\nflw f1, (-SIZE)(x6)"),
+            Err(AssembleError::InvalidImmediate("-SIZE".to_string()))
+        );
+        assert_eq!(
+            assemble(".equ SIZE, 4\nlw x5, (1 << 4 | 0x3)(x6)\nflw f1, (-SIZE)(x6)"),
+            assemble("lw x5, 19(x6)\nflw f1, -4(x6)")
+        );
+        assert_eq!(
+            assemble("ld x5, (1024*2)(x6)"),
+            Err(AssembleError::InvalidImmediate("1024*2".to_string()))
+        );
+        assert!(assemble("ld x5, (2+)(x6)").is_err());
+        assert_eq!(assemble("ld x5, -8(x2)"), Ok(vec![0xff813283]));
+
+        let options = Options {
+            data_base: 0x10000,
+            ..Options::default()
+        };
+        let asm = "sd x5, %lo(Data)(x6)\n.data\n.org 0x1234\nData:\nnop";
+        let program = assemble_program_with(asm, &options).unwrap();
+        assert_eq!(program.instructions, assemble("sd x5, 564(x6)").unwrap());
+    }
+
+    #[test]
+    fn memory_widths() {
+        for mnemonic in MEMORY_MNEMONICS {
+            assert!(
+                instruction::MEMORY_FUNCT3
+                    .iter()
+                    .any(|(known, _)| known == mnemonic),
+                "{}",
+                mnemonic
+            );
+        }
+        for (mnemonic, funct3) in instruction::MEMORY_FUNCT3 {
+            let rd = if mnemonic.starts_with('f') {
+                "f5"
+            } else {
+                "x5"
+            };
+            let line = format!("{} {}, 8(x6)", mnemonic, rd);
+            let inst = assemble_one(&line).unwrap()[0];
+            assert_eq!((inst >> 12) & 0b111, *funct3, "{}", line);
+            assert_eq!(
+                disassemble::disassemble(&[inst], RegNames::Numeric)[0]
+                    .split_whitespace()
+                    .next(),
+                Some(*mnemonic)
+            );
+        }
+    }
+
+    #[test]
+    fn label_addresses() {
+        let data = ".data\n.org 0x1234\nData:\nnop";
+        let options = Options {
+            text_base: 0x1000,
+            data_base: 0x10000,
+            ..Options::default()
+        };
+        let assemble = |asm: &str| {
+            assemble_program_with(&format!("{}\n{}", asm, data), &options).map(|p| p.instructions)
+        };
+        // `Data` is at 0x11234: `la` loads that with `lui`, while `lla`
+        // adds the offset from its `auipc` at 0x1000 or 0x1004.
+        assert_eq!(
+            assemble("la x5, Data"),
+            assemble("lui x5, 0x11\naddi x5, x5, 564")
+        );
+        assert_eq!(
+            assemble("lla x5, Data"),
+            assemble("auipc x5, 0x10\naddi x5, x5, 564")
+        );
+        assert_eq!(
+            assemble("nop\nlla x6, Data"),
+            assemble("nop\nauipc x6, 0x10\naddi x6, x6, 560")
+        );
+        assert!(assemble("lla x5, Missing").is_err());
+    }
+
+    #[test]
+    fn strings() {
+        let program = assemble_program(".asciz \"a//b\" // comment\n.ascii \"\\n\\\"\"").unwrap();
+        assert_eq!(
+            program.instructions,
+            [
+                u32::from_le_bytes(*b"a//b"),
+                u32::from_le_bytes(*b"\0\n\"\0")
+            ]
+        );
+        assert_eq!(assemble(".ascii \"\""), Ok(vec![]));
+        assert_eq!(
+            assemble(".ascii \"\\q\""),
+            Err(AssembleError::InvalidString("\\q".to_string()))
+        );
+        assert_eq!(strip_comment("add x5, x6, x7 // sum"), "add x5, x6, x7 ");
+        assert_eq!(
+            strip_comment(".ascii \"//\\\"//\" // x"),
+            ".ascii \"//\\\"//\" "
+        );
+        assert_eq!(strip_comment("li x5, '/' // slash"), "li x5, '/' ");
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(
+            parse(".byte 0x7f, ',', -1 // comment"),
+            Ok(vec![Statement::Bytes(vec![0x7f, b',', 0xff])])
+        );
+        assert!(assemble(".byte 256").is_err());
+    }
+
+    #[test]
+    fn insn() {
+        assert_eq!(
+            assemble_one(".insn r 0x33, 0, 0, x5, x6, x7"),
+            assemble_one("add x5, x6, x7")
+        );
+        assert_eq!(
+            assemble_one(".insn i 0x13, 0, x5, x6, -1 // addi"),
+            assemble_one("addi x5, x6, -1")
+        );
+        assert_eq!(
+            assemble_one(".insn s 0x23, 3, x5, 8(x6)"),
+            assemble_one("sd x5, 8(x6)")
+        );
+        assert_eq!(
+            assemble_one(".insn s 0x23, 3, x5, -8(x6)"),
+            Ok(vec![0xfe533c23])
+        );
+        assert_eq!(
+            assemble_one(".insn u 0x37, x5, 0x12345"),
+            assemble_one("lui x5, 0x12345")
+        );
+        // Standard B- and J-type layouts, from `llvm-mc`.
+        assert_eq!(
+            assemble_one(".insn b 0x63, 0, x5, x6, 8"),
+            Ok(vec![0x00628463])
+        );
+        assert_eq!(
+            assemble_one(".insn b 0x63, 0, x5, x6, -4096"),
+            Ok(vec![0x80628063])
+        );
+        assert_eq!(assemble_one(".insn j 0x6f, x1, 2048"), Ok(vec![0x001000ef]));
+        assert_eq!(assemble_one(".insn j 0x6f, x1, -2"), Ok(vec![0xfffff0ef]));
+        assert!(assemble_one(".insn r 0x80, 0, 0, x5, x6, x7").is_err());
+        assert!(assemble_one(".insn i 0x13, 0, x5, x6").is_err());
+        assert!(assemble_one(".insn b 0x63, 0, x5, x6, 3").is_err());
+    }
+
+    #[test]
+    fn sized_data() {
+        assert_eq!(
+            assemble(".8byte 0x0123456789ABCDEF"),
+            Ok(vec![0x89abcdef, 0x01234567])
+        );
+        assert_eq!(
+            assemble(".8byte 0xFEDCBA9876543210"),
+            Ok(vec![0x76543210, 0xfedcba98])
+        );
+        assert_eq!(
+            assemble(".8byte -0x8000000000000000"),
+            Ok(vec![0, 0x80000000])
+        );
+        assert!(assemble(".8byte 0x10000000000000000").is_err());
+        assert!(assemble(".8byte -0x8000000000000001").is_err());
+        assert_eq!(assemble(".4byte -1"), assemble(".word 0xffffffff"));
+        // Consecutive data packs into words, with no alignment.
+        let program = assemble_program("Half: .2byte 0x1234, -1, 7\nWord: .4byte 2").unwrap();
+        assert_eq!(program.instructions, vec![0xffff1234, 0x00020007, 0]);
+        assert_eq!(program.symbols["Word"].address, 6);
+        assert!(assemble(".2byte 0x10000").is_err());
+        assert!(assemble(".2byte -32769").is_err());
+        assert!(assemble(".4byte 0x100000000").is_err());
+    }
+
+    #[test]
+    fn packed_data() {
+        let asm =
+            ".byte 1\n.2byte 0x302\nFourth: .byte 4\n.2byte 5\nAfter:\n.byte 6\nEntry:\nnop\nCode:";
+        let program = assemble_program(asm).unwrap();
+        assert_eq!(program.instructions, [0x04030201, 0x00060005, 0]);
+        assert_eq!(program.symbols["Fourth"].address, 3);
+        assert_eq!(program.symbols["After"].address, 6);
+        // An instruction starts on the next word, and so do its labels.
+        assert_eq!(program.symbols["Entry"].address, 8);
+        assert_eq!(program.symbols["Code"].address, 12);
+        let program = assemble_program(".data\n.byte 1\n.text\nnop\n.data\nNext: .byte 2").unwrap();
+        assert_eq!(program.data, [0x0201]);
+        assert_eq!(program.symbols["Next"].address, 1);
+    }
+
+    #[test]
+    fn from_lines() {
+        let lines = vec!["Loop:", "addi x5, x5, -1", "bne x5, x0, Loop"];
+        assert_eq!(
+            assemble_from_lines(&lines),
+            assemble("Loop:\naddi x5, x5, -1\nbne x5, x0, Loop")
+        );
+        assert_eq!(assemble_from_lines(&["Loop: j Loop"]).unwrap().len(), 1);
+        assert!(assemble_from_lines(&["bne x5, x0, Missing"]).is_err());
+    }
+
+    #[test]
+    fn fixed_buffer() {
+        let asm = "Loop:\naddi x5, x5, -1\nbne x5, x0, Loop";
+        let mut out = [0xffff_ffff; 4];
+        assert_eq!(assemble_into(asm, &mut out), Ok(2));
+        assert_eq!(out[..2], assemble(asm).unwrap()[..]);
+        assert_eq!(out[2..], [0xffff_ffff; 2]);
+        let mut out = [0; 2];
+        assert_eq!(assemble_into(asm, &mut out), Ok(2));
+        assert_eq!(out[..], assemble(asm).unwrap()[..]);
+        // Data words take no room in the buffer.
+        let mut out = [0xffff_ffff; 1];
+        assert_eq!(assemble_into("nop\n.data\n.word 7", &mut out), Ok(1));
+        assert_eq!(out, [0]);
+        // A bad label is found before anything is written.
+        let mut out = [0xffff_ffff; 2];
+        assert!(assemble_into("nop\nj Nowhere", &mut out).is_err());
+        assert_eq!(out, [0xffff_ffff; 2]);
+        let mut out = [0; 1];
+        assert_eq!(
+            assemble_into(asm, &mut out),
+            Err(AssembleError::BufferTooSmall {
+                needed: 2,
+                capacity: 1
+            })
+        );
+        assert_eq!(out, [0]);
+    }
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(assemble(""), Ok(vec![]));
+        assert_eq!(
+            assemble("// Nothing yet.\n\n  // Still nothing.\n"),
+            Ok(vec![])
+        );
+        let program = assemble_program("Start:\nEnd:\n").unwrap();
+        assert!(program.instructions.is_empty());
+        assert_eq!(program.symbols["Start"].address, 0);
+        assert_eq!(program.symbols["End"].address, 0);
+    }
+
+    #[test]
+    fn pause() {
+        // A `fence` with only the `w` predecessor bit set, per Zihintpause.
+        assert_eq!(assemble_one("pause // spin"), Ok(vec![0x0100000f]));
+        assert_eq!(
+            disassemble::disassemble(&[0x0100000f], RegNames::Numeric),
+            vec!["pause"]
+        );
+        assert!(assemble_one("pause x5").is_err());
+    }
+
+    #[test]
+    fn address_units() {
+        let asm = "Start:\nnop\nj Next\nnop\nNext:\nlla x5, Start";
+        let words = Options {
+            address_unit: AddressUnit::Word,
+            ..Options::default()
+        };
+        // `Next` is 8 bytes or 2 words past the `j`, and `Start` 12 bytes
+        // or 3 words before the `auipc`.
+        assert_eq!(
+            assemble(asm),
+            Ok(vec![0, 0x0080006f, 0, 0x00000297, 0xff428293])
+        );
+        assert_eq!(
+            assemble_program_with(asm, &words).map(|p| p.instructions),
+            Ok(vec![0, 0x0040006f, 0, 0x00000297, 0xffd28293])
+        );
+        // Absolute addresses are scaled too: `Table` is at byte 0x100, or
+        // word 0x40. Conditional branches keep their custom encoding, which
+        // counts instructions in either unit.
+        let absolute = "lui x5, %hi(Table)\naddi x5, x5, %lo(Table)\n.org 0x100\nTable:\nnop";
+        assert_eq!(
+            assemble(absolute).map(|words| words[..2].to_vec()),
+            Ok(vec![0x000002b7, 0x10028293])
+        );
+        assert_eq!(
+            assemble_program_with(absolute, &words).map(|p| p.instructions[..2].to_vec()),
+            Ok(vec![0x000002b7, 0x04028293])
+        );
+        assert_eq!(
+            line_addresses(asm, &words),
+            Ok(vec![(2, 0), (3, 1), (4, 2), (6, 3)])
+        );
+        assert!("nibble".parse::<AddressUnit>().is_err());
+    }
+
+    #[test]
+    fn same_line_labels() {
+        let options = Options {
+            data_base: 0x10000,
+            ..Options::default()
+        };
+        let program = assemble_program_with(
+            "lw x5, Value\nLoop: addi x5, x5, -1\nbne x5, x0, Loop\n\
+             .data\nFirst: .word 0x10\nValue: .word 0x12345678, -1 // two words",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(program.symbols["Loop"].address, 8);
+        assert_eq!(program.symbols["Value"].address, 0x10004);
+        assert_eq!(program.data, vec![0x10, 0x12345678, 0xffffffff]);
+        assert_eq!(
+            program.instructions[..2],
+            assemble("lui x5, 0x10\nlw x5, 4(x5)").unwrap()[..]
+        );
+        assert_eq!(parse("Skip: 1: nop"), parse("Skip:\n1:\nnop"));
+        assert!(assemble(".word 0x100000000").is_err());
+    }
+
+    #[test]
+    fn char_literals() {
+        assert_eq!(parse_immediate("'A'"), Ok(65));
+        assert_eq!(parse_immediate("'\\n'"), Ok(10));
+        assert_eq!(parse_immediate("'\\''"), Ok(39));
+        assert_eq!(
+            assemble_one("addi x5, x0, 'A'"),
+            assemble_one("addi x5, x0, 65")
+        );
+        assert_eq!(
+            assemble_one("li x5, '\\n' // newline"),
+            assemble_one("li x5, 10")
+        );
+        assert_eq!(
+            assemble_one("addi x5, x0, 'ab'"),
+            Err(AssembleError::InvalidImmediate("'ab'".to_string()))
+        );
+        assert!(assemble_one("addi x5, x0, ''").is_err());
+        assert!(assemble_one("addi x5, x0, '\\q'").is_err());
+    }
+
+    #[test]
+    fn digit_separators() {
+        assert_eq!(parse_immediate("1_000"), Ok(1000));
+        assert_eq!(parse_immediate("0x1000_0000"), Ok(0x1000_0000));
+        assert!(parse_immediate("_100").is_err());
+        assert!(parse_immediate("100_").is_err());
+        assert!(parse_immediate("0x_100").is_err());
+        assert_eq!(
+            assemble_one("ld x5, 1_0(x6)"),
+            assemble_one("ld x5, 10(x6)")
+        );
+        assert_eq!(
+            assemble_one("addi x5, x0, 1_000"),
+            assemble_one("addi x5, x0, 1000")
+        );
+        assert_eq!(
+            assemble_one("addi x5, x0, _100"),
+            Err(AssembleError::InvalidInstruction(
+                "addi x5, x0, _100".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn symbol_metadata() {
+        let asm = ".globl main\n.type main, @function\nmain:\nadd x5, x6, x7\nnop\n.size main, .-main\n.data\ntable:\n.word 1, 2, 3, 4\n.type table, %object\n.size table, 16";
+        assert_eq!(assemble(asm), assemble("main:\nadd x5, x6, x7\nnop"));
+        let statements = parse(asm).unwrap();
+        assert_eq!(
+            statements[1],
+            Statement::Type("main".to_string(), SymbolType::Function)
+        );
+        assert_eq!(
+            statements[5],
+            Statement::Size("main".to_string(), SymbolSize::Since("main".to_string()))
+        );
+        assert_eq!(
+            statements[10],
+            Statement::Size("table".to_string(), SymbolSize::Bytes(16))
+        );
+        let program = assemble_program(asm).unwrap();
+        assert_eq!(program.symbols["main"].kind, Some(SymbolType::Function));
+        assert_eq!(program.symbols["main"].size, Some(8));
+        assert_eq!(program.symbols["table"].kind, Some(SymbolType::Object));
+        assert_eq!(program.symbols["table"].size, Some(16));
+        let program = assemble_program("f:\nnop").unwrap();
+        assert_eq!(
+            (program.symbols["f"].kind, program.symbols["f"].size),
+            (None, None)
+        );
+        assert_eq!(
+            assemble(".type main, function").unwrap_err().to_string(),
+            "Invalid Directive: `.type main, function`, expected `.type <symbol>, @function`, `@object` or `@notype`"
+        );
+        assert_eq!(
+            assemble(".size main").unwrap_err().to_string(),
+            "Invalid Directive: `.size main`, expected `.size <symbol>, <bytes>` or `.size <symbol>, .-<label>`"
+        );
+        assert!(matches!(
+            assemble(".type mian, @function\nmain:"),
+            Err(AssembleError::InvalidLabel { .. })
+        ));
+    }
+
+    #[test]
+    fn globals() {
+        let program =
+            assemble_program(".globl Main\nMain:\nnop\nLoop:\nnop\n.global Loop").unwrap();
+        let symbols: Vec<_> = program.symbols.iter().collect();
+        assert_eq!(
+            symbols,
+            [
+                (
+                    &"Loop".to_string(),
+                    &Symbol {
+                        address: 4,
+                        global: true,
+                        kind: None,
+                        size: None,
+                    }
+                ),
+                (
+                    &"Main".to_string(),
+                    &Symbol {
+                        address: 0,
+                        global: true,
+                        kind: None,
+                        size: None,
+                    }
+                ),
+            ]
+        );
+        let program = assemble_program("Main:\nnop").unwrap();
+        assert!(!program.symbols["Main"].global);
+        assert!(assemble(".globl Mian\nMain:").is_err());
+    }
+
+    #[test]
+    fn unbalanced_parentheses() {
+        let err = assemble_one("ld x5, 40(x6").unwrap_err();
+        assert_eq!(err, AssembleError::UnbalancedParentheses("ld".to_string()));
+        assert_eq!(
+            err.to_string(),
+            "ld: unbalanced parentheses in address operand"
+        );
+        let err = assemble_one("sd x5, 40x6) // (").unwrap_err();
+        assert_eq!(err, AssembleError::UnbalancedParentheses("sd".to_string()));
+        let err = assemble_one("ld x5, 40[x6]").unwrap_err();
+        assert!(matches!(err, AssembleError::InvalidInstruction(_)));
+    }
+
+    #[test]
+    fn registers() {
+        assert_eq!(
+            assemble_one("add fp, gp, tp"),
+            assemble_one("add x8, x3, x4")
+        );
+        assert_eq!(
+            assemble_one("add s0, zero, a0"),
+            assemble_one("add x8, x0, x10")
+        );
+        assert_eq!(assemble_one("ld ra, 8(sp)"), assemble_one("ld x1, 8(x2)"));
+        assert_eq!(
+            assemble_one("add x5, x6, x32"),
+            Err(AssembleError::InvalidRegister("x32".to_string()))
+        );
+    }
+
+    #[test]
+    fn high_registers() {
+        // As reported by `llvm-mc -triple=riscv64 -mattr=+m -show-encoding`,
+        // except branches, which use the processor's encoding.
+        let asm = "add x10, x15, x28\n\
+                   beq x10, x31, Skip\n\
+                   sub x31, x10, x15\n\
+                   Skip:\n\
+                   blt x28, x15, Skip\n\
+                   addi x28, x31, 5\n\
+                   ld x15, 8(x28)\n\
+                   sd x31, 16(x10)\n\
+                   lui x28, 0x12\n\
+                   mul x31, x28, x15\n\
+                   sltu x10, x15, x31";
+        let expected = [
+            0x01c78533, 0x01f50163, 0x40f50fb3, 0x02fe4063, 0x005f8e13, 0x008e3783, 0x01f53823,
+            0x00012e37, 0x02fe0fb3, 0x01f7b533,
+        ];
+        assert_eq!(assemble(asm).unwrap(), expected);
+    }
+
+    #[test]
+    fn f_operands() {
+        assert_eq!(
+            assemble_one("fadd.s f1, x2, f3"),
+            Err(AssembleError::InvalidRegister("x2".to_string()))
+        );
+        assert_eq!(
+            assemble_one("flw f1, 0(f2)"),
+            Err(AssembleError::FpRegister("f2".to_string()))
+        );
+        assert!(assemble_one("flw f1, 2048(x2)").is_err());
+        assert!(assemble_one("fadd.s f1, f2, f3, up").is_err());
+        // `flw f0` writes a floating-point register, not `x0`.
+        assert!(assemble_program("flw f0, 0(x2)")
+            .unwrap()
+            .warnings
+            .is_empty());
+    }
+
+    /// One example of every instruction, written as the disassembler prints
+    /// it. Pseudo-instructions decode to the instructions they expand to.
+    const ROUND_TRIPS: &[&str] = &[
+        "nop",
+        "ld x5, 8(x6)",
+        "lw x5, 2047(x6)",
+        "sd x5, 16(x6)",
+        "and x5, x6, x7",
+        "or x5, x6, x7",
+        "add x5, x6, x7",
+        "sub x5, x6, x7",
+        "slt x5, x6, x7",
+        "sltu x5, x6, x7",
+        "mul x5, x6, x7",
+        "mulh x5, x6, x7",
+        "mulhsu x5, x6, x7",
+        "mulhu x5, x6, x7",
+        "div x5, x6, x7",
+        "divu x5, x6, x7",
+        "rem x5, x6, x7",
+        "remu x5, x6, x7",
+        "L0:\nbeq x5, x6, L0",
+        "L0:\nbne x5, x6, L0",
+        "L0:\nblt x5, x6, L0",
+        "L0:\nbge x5, x6, L0",
+        "L0:\nbltu x5, x6, L0",
+        "L0:\nbgeu x5, x6, L0",
+        "addi x5, x6, -1",
+        "auipc x5, 0x12345",
+        "lui x5, 0xfffff",
+        "L0:\njal x1, L0",
+        "ecall",
+        "ebreak",
+        "wfi",
+        "mret",
+        "sret",
+        "sfence.vma",
+        "sfence.vma x5, x6",
+        "pause",
+        "flw f1, 0(x2)",
+        "fsw f1, 4(x2)",
+        "fadd.s f1, f2, f3",
+        "fsub.s f4, f5, f6, rtz",
+        "fmul.s f7, f8, f9, rdn",
+        "fdiv.s f31, f30, f29, rup",
+        "andn x5, x6, x7",
+        "orn x5, x6, x7",
+        "xnor x5, x6, x7",
+        "min x5, x6, x7",
+        "minu x5, x6, x7",
+        "max x5, x6, x7",
+        "maxu x5, x6, x7",
+        "clz x5, x6",
+        "ctz x5, x6",
+        "cpop x5, x6",
+    ];
+
+    #[test]
+    fn round_trips() {
+        let options = Options {
+            extensions: vec![Extension::Zbb],
+            ..Options::default()
+        };
+        let pseudos = [
+            "sltz", "sgtz", "sgt", "sgtu", "bgt", "ble", "bgtu", "bleu", "li", "la", "lla", "j",
+            "call", "tail",
+        ];
+        for (mnemonic, ..) in MNEMONICS.iter().chain(ZBB_MNEMONICS) {
+            let example = |example: &&str| {
+                let line = example.lines().last().unwrap_or_default();
+                line.split_whitespace().next() == Some(*mnemonic)
+            };
+            assert!(
+                pseudos.contains(mnemonic) || ROUND_TRIPS.iter().any(example),
+                "no example of `{}`",
+                mnemonic
+            );
+        }
+        for example in ROUND_TRIPS {
+            let words = assemble_program_with(example, &options)
+                .unwrap()
+                .instructions;
+            assert_eq!(
+                disassemble::disassemble(&words, RegNames::Numeric).join("\n"),
+                *example
+            );
+        }
+    }
+
+    #[test]
+    fn dispatch() {
+        // Zbb is parsed as an extension.
+        for (mnemonic, _, subset) in MNEMONICS {
+            if *subset != Subset::Zbb {
+                assert!(DISPATCH.contains_key(mnemonic), "{}", mnemonic);
+            }
+        }
+        for line in [
+            "nop",
+            "ecall",
+            "ld x5, 8(x6)",
+            "lw x5, Data",
+            "sd x5, 8(x6)",
+            "fadd.s f1, f2, f3",
+            "sltu x5, x6, x7",
+            "sgtz x5, x6",
+            "remu x5, x6, x7",
+            "bleu x5, x6, Loop",
+            "li x5, 0x12345",
+            "tail Loop",
+        ] {
+            assert!(parse_instruction(line).unwrap().is_some(), "{}", line);
+        }
+        assert_eq!(parse_instruction("frob x5, x6"), Ok(None));
+        assert_eq!(
+            parse_line("frob x5, x6"),
+            Err(AssembleError::InvalidInstruction("frob x5, x6".to_string()))
+        );
+    }
+
+    #[test]
+    fn syntax() {
+        let syntax = instruction_syntax(&Options::default());
+        for mnemonic in ["nop", "ld", "sd", "and", "or", "add", "sub", "beq", "blt"] {
+            assert!(syntax
+                .iter()
+                .any(|line| line.split(' ').next() == Some(mnemonic)));
+        }
+        assert!(syntax.contains(&"ld rd, offset(rs1)".to_string()));
+        assert!(!syntax.iter().any(|line| line.starts_with("andn")));
+        let options = Options {
+            extensions: vec![Extension::Zbb],
+            ..Options::default()
+        };
+        assert!(instruction_syntax(&options).contains(&"andn rd, rs1, rs2".to_string()));
+    }
+
+    #[test]
+    fn isa_comment() {
+        let with_isa = |asm: &str| {
+            let isa = declared_isa(asm).unwrap().unwrap();
+            let options = Options {
+                profile: isa.profile,
+                extensions: isa.extensions,
+                ..Options::default()
+            };
+            assemble_program_with(asm, &options).map(|p| p.instructions)
+        };
+        assert_eq!(
+            with_isa("// risc-v: rv64im\nmul x5, x6, x7"),
+            assemble("mul x5, x6, x7")
+        );
+        assert_eq!(
+            with_isa("// risc-v: rv32i\nmul x5, x6, x7"),
+            Err(AssembleError::OutsideProfile("mul".to_string()))
+        );
+        assert!(with_isa("  //risc-v: rv64i_zbb\nandn x5, x6, x7").is_ok());
+        assert_eq!(
+            with_isa("// risc-v: rv32i_zbb\nandn x5, x6, x7"),
+            with_isa("// risc-v: rv64i_zbb\nandn x5, x6, x7")
+        );
+        assert_eq!(
+            with_isa("// risc-v: rv32i\nandn x5, x6, x7"),
+            Err(AssembleError::InvalidInstruction(
+                "andn x5, x6, x7".to_string()
+            ))
+        );
+        assert_eq!(declared_isa("nop\n// risc-v: rv32i"), Ok(None));
+        assert!("rv64ic".parse::<IsaString>().is_err());
+        assert!("rv32i_zfoo".parse::<IsaString>().is_err());
+        assert!("x86".parse::<IsaString>().is_err());
+    }
+
+    #[test]
+    fn profile() {
+        let options = Options {
+            profile: Some(Profile::Rv32i),
+            ..Options::default()
+        };
+        let assemble = |asm| assemble_program_with(asm, &options).map(|p| p.instructions);
+        assert_eq!(
+            assemble("add x5, x6, x7"),
+            Ok(assemble_one("add x5, x6, x7").unwrap())
+        );
+        assert!(assemble("li x5, 0x12345\nlw x6, 0(x5)\nj Main\nMain:").is_ok());
+        for asm in [
+            "mul x5, x6, x7",
+            "ld x5, 0(x6)",
+            "sd x5, 0(x6)",
+            "div x5, x6, x7",
+        ] {
+            let mnemonic = asm.split(' ').next().unwrap().to_string();
+            assert_eq!(assemble(asm), Err(AssembleError::OutsideProfile(mnemonic)));
+        }
+        assert!(!instruction_syntax(&options)
+            .iter()
+            .any(|line| line.starts_with("mul ")));
+    }
+
+    #[test]
+    fn zbb() {
+        let options = Options {
+            extensions: vec![Extension::Zbb],
+            ..Options::default()
+        };
+        let asm = "andn x5, x6, x7\nmin x5, x6, x7\ncpop x5, x6";
+        // As reported by `llvm-mc -triple=riscv64 -mattr=+zbb -show-encoding`.
+        assert_eq!(
+            assemble_program_with(asm, &options).unwrap().instructions,
+            [
+                0b0100000_00111_00110_111_00101_0110011,
+                0b0000101_00111_00110_100_00101_0110011,
+                0b0110000_00010_00110_001_00101_0010011,
+            ]
+        );
+        assert_eq!(
+            assemble("andn x5, x6, x7"),
+            Err(AssembleError::InvalidInstruction(
+                "andn x5, x6, x7".to_string()
+            ))
+        );
+        assert!(assemble("min:\nj min").is_ok());
+    }
+
+    #[test]
+    fn fp_registers() {
+        assert_eq!(resolve_fp_register("f1"), Some(1));
+        assert_eq!(resolve_fp_register("f31"), Some(31));
+        assert_eq!(resolve_fp_register("fa0"), Some(10));
+        assert_eq!(resolve_fp_register("ft11"), Some(31));
+        assert_eq!(resolve_fp_register("f32"), None);
+        assert_eq!(resolve_fp_register("f"), None);
+        assert_eq!(resolve_register("f1"), None);
+        assert_eq!(resolve_fp_register("x1"), None);
+        assert_eq!(
+            assemble_one("add x5, f1, x7"),
+            Err(AssembleError::FpRegister("f1".to_string()))
+        );
+        assert_eq!(
+            AssembleError::FpRegister("fa0".to_string()).to_string(),
+            "Invalid Register: `fa0` is a floating-point register"
+        );
+    }
+
+    #[test]
+    fn expanded_addi() {
+        let imm = |word: u32| word >> 20;
+        assert_eq!(imm(assemble_one("addi x5, x6, -1").unwrap()[0]), 0xfff);
+        // `li` expands to the same `addi`, alone or after a `lui`.
+        assert_eq!(imm(assemble_one("li x5, -1").unwrap()[0]), 0xfff);
+        assert_eq!(imm(assemble_one("li x5, 0x12fff").unwrap()[1]), 0xfff);
+        assert_eq!(assemble_one("li x5, -1"), assemble_one("addi x5, x0, -1"));
+    }
+
+    #[test]
+    fn li() {
+        // Expected words from `llvm-mc -triple=riscv32 -show-encoding`.
+        assert_eq!(assemble_one("li x5, 0x7FF"), Ok(vec![0x7ff00293]));
+        assert_eq!(
+            assemble_one("li x5, 0x800"),
+            Ok(vec![0x000012b7, 0x80028293])
+        );
+        assert_eq!(assemble_one("li x5, -1"), Ok(vec![0xfff00293]));
+        assert_eq!(assemble_one("li x5, 0xFFFFF800"), Ok(vec![0x80000293]));
+        assert_eq!(
+            assemble_one("li x5, 0x12345FFF"),
+            Ok(vec![0x123462b7, 0xfff28293])
+        );
+        assert_eq!(assemble_one("li x5, 0x12345000"), Ok(vec![0x123452b7]));
+        assert!(assemble_one("li x5, 0x100000000").is_err());
+    }
+}
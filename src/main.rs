@@ -2,332 +2,1618 @@
 extern crate lazy_static;
 
 use regex::Regex;
-use std::collections::HashMap;
-use std::convert::TryInto;
+use risc_v_assembler::disassemble::{self, RegNames};
+use risc_v_assembler::instruction::Relocation;
+use risc_v_assembler::isa_spec::parse_spec;
+use risc_v_assembler::output::{emit, OutputFormat};
+use risc_v_assembler::{
+    assemble_program_located, assemble_unresolved, branch_targets, cross_references, declared_isa,
+    instruction_syntax, line_addresses, listing, parse_with, pending_relocations, relocations,
+    AddressUnit, Conditionals, Extension, LocatedError, OffsetFormat, Options, PendingRelocation,
+    Profile, Program, Section, Statement, SymbolSize,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{self, File};
-use std::io::prelude::*;
-use std::path::PathBuf;
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
 use structopt::StructOpt;
 
+/// The size in bytes of an encoded instruction word.
+const WORD_SIZE: usize = 4;
+
 lazy_static! {
-    static ref REG: &'static str = r"\s*x(\d+)\s*";
-    static ref SEP: &'static str = r"\s*,\s*";
-    static ref NUM: &'static str = r"\s*(\d+)\s*";
-    static ref LAB: &'static str = r"\s*(\w+)\s*";
-    static ref COM: &'static str = r"\s*(//.*)?";
-    static ref NOP_STR: String = format!(r"^\s*nop\s*{c}$", c=*COM);
-    static ref NOP_REGEX: Regex = Regex::new(&NOP_STR).unwrap(); // nop
-    static ref LD_STR: String = format!(r"^\s*ld\s+{r}{s}{n}\({r}\)\s*{c}$", r=*REG, s=*SEP, n=*NUM, c=*COM);
-    static ref LD_REGEX: Regex = Regex::new(&LD_STR).unwrap(); // ld x5, 40(x6)
-    static ref SD_STR: String = format!(r"^\s*sd\s+{r}{s}{n}\({r}\)\s*{c}$", r=*REG, s=*SEP, n=*NUM, c=*COM);
-    static ref SD_REGEX: Regex = Regex::new(&SD_STR).unwrap(); // sd x5, 40(x6)
-    static ref AND_STR: String = format!(r"^\s*and\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
-    static ref AND_REGEX: Regex = Regex::new(&AND_STR).unwrap(); // and x5, x6, x7
-    static ref OR_STR: String = format!(r"^\s*or\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
-    static ref OR_REGEX: Regex = Regex::new(&OR_STR).unwrap(); // or x5, x6, x7
-    static ref ADD_STR: String = format!(r"^\s*add\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
-    static ref ADD_REGEX: Regex = Regex::new(&ADD_STR).unwrap(); // add x5, x6, x7
-    static ref SUB_STR: String = format!(r"^\s*sub\s+{r}{s}{r}{s}{r}{c}$", r=*REG, s=*SEP, c=*COM);
-    static ref SUB_REGEX: Regex = Regex::new(&SUB_STR).unwrap(); // sub x5, x6, x7
-    static ref BEQ_STR: String = format!(r"^\s*beq\s+{r}{s}{r}{s}{l}{c}$", r=*REG, s=*SEP, l=*LAB, c=*COM);
-    static ref BEQ_REGEX: Regex = Regex::new(&BEQ_STR).unwrap(); // beq x5, x6, Label
-    static ref BLT_STR: String = format!(r"^\s*blt\s+{r}{s}{r}{s}{l}{c}$", r=*REG, s=*SEP, l=*LAB, c=*COM);
-    static ref BLT_REGEX: Regex = Regex::new(&BLT_STR).unwrap(); // blt x5, x6, Label
-    static ref LABEL_STR: String = format!(r"^{l}:{c}$", l=*LAB, c=*COM);
-    static ref LABEL_REGEX: Regex = Regex::new(&LABEL_STR).unwrap(); // Label:
+    static ref INCLUDE_REGEX: Regex = Regex::new(r#"^\s*\.include(_once)?\s+"([^"]+)"\s*(//.*)?$"#).unwrap(); // .include "lib.asm"
+    static ref INCBIN_REGEX: Regex = Regex::new(r#"^\s*\.incbin\s+"([^"]+)"\s*(//.*)?$"#).unwrap(); // .incbin "blob.bin"
 }
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "risc-v-assembler")]
 struct Opt {
-    #[structopt(parse(from_os_str))]
-    asm: PathBuf,
+    #[structopt(parse(from_os_str), required_unless = "list-instructions")]
+    asm: Option<PathBuf>,
+
+    /// Print every supported instruction with its operands, and exit.
+    #[structopt(long)]
+    list_instructions: bool,
 
     #[structopt(short, long, parse(from_os_str))]
     obj: Option<PathBuf>,
 
+    /// The extension of the default object file, or a name template beside
+    /// the source where `{stem}` is its file stem, such as `{stem}-rom.bin`.
+    #[structopt(long, default_value = "obj")]
+    obj_extension: String,
+
+    /// Fail unless `--obj` is given, instead of writing next to the source.
+    #[structopt(long)]
+    no_default_obj: bool,
+
+    /// Drop trailing words that are zero or `addi x0, x0, 0`, unless a
+    /// branch or jump goes to them.
+    #[structopt(long)]
+    trim_trailing_nops: bool,
+
     #[structopt(long)]
     padding: Option<usize>,
+
+    /// Zero-pad the output to a multiple of this many words, after any
+    /// `--padding`.
+    #[structopt(long)]
+    pad_alignment: Option<usize>,
+
+    /// Pad binary output to a ROM image of this many bytes.
+    #[structopt(long)]
+    rom_size: Option<usize>,
+
+    /// The byte `--rom-size` pads with, by default that of erased flash.
+    #[structopt(long, default_value = "0xff", parse(try_from_str = parse_fill_byte))]
+    fill_byte: u8,
+
+    #[structopt(
+        short = "I",
+        long = "include-path",
+        number_of_values = 1,
+        parse(from_os_str)
+    )]
+    include_paths: Vec<PathBuf>,
+
+    /// Define a symbol for `.ifdef`, as `name` or `name=value`.
+    #[structopt(short = "D", number_of_values = 1)]
+    defines: Vec<String>,
+
+    #[structopt(long)]
+    disassemble: bool,
+
+    #[structopt(long, default_value = "numeric")]
+    reg_names: RegNames,
+
+    /// With `--disassemble`, compare the output against an expected
+    /// assembly file instead of printing it.
+    #[structopt(long, parse(from_os_str))]
+    expect: Option<PathBuf>,
+
+    #[structopt(long, default_value = "text")]
+    format: OutputFormat,
+
+    /// The number of bits each word is zero-padded to in text output.
+    #[structopt(long, default_value = "32", parse(try_from_str = parse_output_width))]
+    output_width: usize,
+
+    #[structopt(long, default_value = "PROGRAM")]
+    array_name: String,
+
+    #[structopt(long)]
+    symbols: bool,
+
+    /// Print every label with the addresses of the instructions that refer
+    /// to it.
+    #[structopt(long)]
+    xref: bool,
+
+    #[structopt(long)]
+    dump_ast: bool,
+
+    /// Print the label fixups pending before labels are resolved.
+    #[structopt(long)]
+    dump_relocations: bool,
+
+    /// Write the label fixups pending before labels are resolved as JSON,
+    /// with standard RISC-V relocation types. They are written even if a
+    /// label is not defined, as for symbols in another object.
+    #[structopt(long, parse(from_os_str))]
+    relocations_json: Option<PathBuf>,
+
+    /// Write the object without resolving labels, leaving their fields zero
+    /// for a linker, so labels may be defined in another object.
+    #[structopt(long)]
+    no_resolve: bool,
+
+    #[structopt(long)]
+    werror: bool,
+
+    #[structopt(long)]
+    instruction_trace: bool,
+
+    #[structopt(long)]
+    stats: bool,
+
+    /// Note when the source assembles to no instructions, as one holding
+    /// only comments and labels does.
+    #[structopt(short, long)]
+    verbose: bool,
+
+    #[structopt(long)]
+    relax_branches: bool,
+
+    /// The radix of offsets in diagnostics: `decimal` or `hex`.
+    #[structopt(long, default_value = "decimal")]
+    offset_format: OffsetFormat,
+
+    /// Write a listing of addresses, encodings and source lines.
+    #[structopt(long, parse(from_os_str))]
+    listing: Option<PathBuf>,
+
+    /// Write the address of every source line that produces code or data,
+    /// as `file:line address`.
+    #[structopt(long, alias = "dry-run-addresses", parse(from_os_str))]
+    line_map: Option<PathBuf>,
+
+    /// The encoding of source files: `utf8` or `latin1`.
+    #[structopt(long, default_value = "utf8")]
+    input_encoding: InputEncoding,
+
+    /// Write the words of each output last first, for loaders that fill
+    /// memory downward. Branch offsets are not recomputed.
+    #[structopt(long)]
+    reverse_output: bool,
+
+    /// Warn about branches and jumps to the very next instruction.
+    #[structopt(long)]
+    warn_zero_branches: bool,
+
+    /// Warn about labels that are never referenced, other than `.globl`
+    /// ones.
+    #[structopt(long)]
+    warn_unused_labels: bool,
+
+    /// Warn about stores based on `x0` at a positive offset below this many
+    /// bytes, such as `sd x1, 8(x0)`.
+    #[structopt(long)]
+    warn_null_stores: Option<u32>,
+
+    /// Warn about instructions that write `x0`, discarding their result,
+    /// such as `addi x0, x1, 5`.
+    #[structopt(long)]
+    warn_x0_writes: bool,
+
+    /// What an address counts: `byte`, or `word` for word-addressed
+    /// instruction memory.
+    #[structopt(long, default_value = "byte")]
+    address_unit: AddressUnit,
+
+    /// Reject instructions outside a base ISA; only `rv32i` is supported.
+    #[structopt(long)]
+    profile: Option<Profile>,
+
+    /// Enable an optional extension; only `zbb` is supported.
+    #[structopt(long = "ext", number_of_values = 1)]
+    extensions: Vec<Extension>,
+
+    /// A file describing additional instruction encodings.
+    #[structopt(long, parse(from_os_str))]
+    isa_spec: Option<PathBuf>,
+
+    /// The byte address of the `.text` section.
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_address))]
+    text_base: usize,
+
+    /// The byte address of the `.data` section.
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_address))]
+    data_base: usize,
+
+    /// A file of `name = address` lines predefining labels outside the
+    /// program.
+    #[structopt(long, parse(from_os_str))]
+    seed_labels: Option<PathBuf>,
+
+    /// Skip unknown directives with a warning instead of failing.
+    #[structopt(long)]
+    ignore_unknown_directives: bool,
+
+    /// Where to write the `.data` section, by default next to the object
+    /// file with a `.data.obj` extension.
+    #[structopt(long, parse(from_os_str))]
+    data_obj: Option<PathBuf>,
 }
 
-fn main() {
-    let opt = Opt::from_args();
-    let asm = fs::read_to_string(&opt.asm).unwrap();
-    let mut labels = HashMap::new();
-    let mut instructions = Vec::new();
-    for line in asm.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with("//") {
-            continue;
-        } else if NOP_REGEX.is_match(line) {
-            instructions.push((0, None));
-        } else if let Some(inst) = parse_ld(line) {
-            instructions.push((inst, None));
-        } else if let Some(inst) = parse_sd(line) {
-            instructions.push((inst, None));
-        } else if let Some(inst) = parse_and(line) {
-            instructions.push((inst, None));
-        } else if let Some(inst) = parse_or(line) {
-            instructions.push((inst, None));
-        } else if let Some(inst) = parse_add(line) {
-            instructions.push((inst, None));
-        } else if let Some(inst) = parse_sub(line) {
-            instructions.push((inst, None));
-        } else if let Some((inst, label)) = parse_beq(line) {
-            instructions.push((inst, Some(label)));
-        } else if let Some((inst, label)) = parse_blt(line) {
-            instructions.push((inst, Some(label)));
-        } else if let Some(caps) = LABEL_REGEX.captures(line) {
-            labels.insert(caps[1].to_string(), instructions.len());
+/// Reports warnings and counts them, so that `--werror` can fail the run.
+#[derive(Debug, Default)]
+struct Warnings {
+    count: usize,
+}
+
+impl Warnings {
+    fn warn(&mut self, message: &str) {
+        eprintln!("Warning: {}", message);
+        self.count += 1;
+    }
+
+    fn exit_code(&self, werror: bool) -> i32 {
+        if werror && self.count > 0 {
+            1
         } else {
-            panic!("Invalid Instruction: `{}`", line);
+            0
         }
     }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputEncoding {
+    Utf8,
+    Latin1,
+}
 
-    let mut instructions = transform_labels(instructions, labels);
+impl FromStr for InputEncoding {
+    type Err = String;
 
-    if let Some(size) = opt.padding {
-        if instructions.len() > size {
-            eprintln!("Warning: the padding length is smaller than the number of instructions.")
-        }
-        while instructions.len() < size {
-            instructions.push(0);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf8" => Ok(InputEncoding::Utf8),
+            "latin1" => Ok(InputEncoding::Latin1),
+            _ => Err(format!(
+                "Invalid Input Encoding: `{}` (expected `utf8` or `latin1`)",
+                s
+            )),
         }
     }
+}
 
-    let obj_path = match opt.obj {
+fn main() {
+    let opt = Opt::from_args();
+    if opt.list_instructions {
+        let options = Options {
+            extensions: opt.extensions.clone(),
+            profile: opt.profile,
+            ..Options::default()
+        };
+        for syntax in instruction_syntax(&options) {
+            println!("{}", syntax);
+        }
+        return;
+    }
+    let asm_path = opt.asm.clone().unwrap();
+    if opt.disassemble {
+        let obj = or_exit(read_text(&asm_path));
+        let instructions: Vec<u32> = obj
+            .lines()
+            .map(|line| {
+                u32::from_str_radix(line.trim(), 2)
+                    .map_err(|_| format!("Invalid Word: `{}`", line.trim()))
+            })
+            .map(or_exit)
+            .collect();
+        let lines = disassemble::disassemble(&instructions, opt.reg_names);
+        if let Some(expect) = &opt.expect {
+            let expected = or_exit(read_text(expect));
+            if let Err(err) = check_expected(&lines, &expected) {
+                eprintln!("Error: {}", err);
+                process::exit(1);
+            }
+            return;
+        }
+        for line in lines {
+            println!("{}", line);
+        }
+        return;
+    }
+    let defines = match parse_defines(&opt.defines) {
+        Ok(defines) => defines,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+    let source = match read_source(
+        &asm_path,
+        &opt.include_paths,
+        opt.input_encoding,
+        &mut HashSet::new(),
+        &defines,
+    ) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+    let asm = source.text.as_str();
+    let obj_path = match opt.obj.clone() {
         Some(obj) => obj,
-        None => {
-            let mut path = opt.asm.clone();
-            path.set_extension("obj");
-            path
+        None if opt.no_default_obj => {
+            eprintln!("Error: `--no-default-obj` needs `--obj`");
+            process::exit(1);
         }
+        None => default_obj_path(&asm_path, &opt.obj_extension),
     };
-    let mut obj = File::create(obj_path).unwrap();
-    for inst in instructions {
-        writeln!(&mut obj, "{:0>32b}", inst).unwrap();
-    }
-}
-
-fn parse_ld(line: &str) -> Option<u32> {
-    if let Some(caps) = LD_REGEX.captures(line) {
-        let rd: u32 = caps[1].parse().unwrap();
-        let imm: u32 = caps[2].parse().unwrap();
-        let rs1: u32 = caps[3].parse().unwrap();
-        let mut instruction: u32 = 0;
-        instruction |= 0b0000011;
-        instruction |= rd << 7;
-        instruction |= 0b011 << 12;
-        instruction |= rs1 << 15;
-        instruction |= imm << 20;
-        Some(instruction)
-    } else {
-        None
-    }
-}
-
-fn parse_sd(line: &str) -> Option<u32> {
-    if let Some(caps) = SD_REGEX.captures(line) {
-        let rs2: u32 = caps[1].parse().unwrap();
-        let imm: u32 = caps[2].parse().unwrap();
-        let rs1: u32 = caps[3].parse().unwrap();
-        let mut instruction: u32 = 0;
-        instruction |= 0b0100011;
-        instruction |= (imm & 0b00000000_00000000_00000000_00011111) << 7;
-        instruction |= 0b011 << 12;
-        instruction |= rs1 << 15;
-        instruction |= rs2 << 20;
-        instruction |= (imm & 0b00000000_00000000_00001111_11100000) << 20;
-        Some(instruction)
-    } else {
-        None
-    }
-}
-
-fn parse_and(line: &str) -> Option<u32> {
-    if let Some(caps) = AND_REGEX.captures(line) {
-        let rd: u32 = caps[1].parse().unwrap();
-        let rs1: u32 = caps[2].parse().unwrap();
-        let rs2: u32 = caps[3].parse().unwrap();
-        let mut instruction: u32 = 0;
-        instruction |= 0b0110011;
-        instruction |= rd << 7;
-        instruction |= 0b111 << 12;
-        instruction |= rs1 << 15;
-        instruction |= rs2 << 20;
-        Some(instruction)
-    } else {
-        None
-    }
-}
-
-fn parse_or(line: &str) -> Option<u32> {
-    if let Some(caps) = OR_REGEX.captures(line) {
-        let rd: u32 = caps[1].parse().unwrap();
-        let rs1: u32 = caps[2].parse().unwrap();
-        let rs2: u32 = caps[3].parse().unwrap();
-        let mut instruction: u32 = 0;
-        instruction |= 0b0110011;
-        instruction |= rd << 7;
-        instruction |= 0b110 << 12;
-        instruction |= rs1 << 15;
-        instruction |= rs2 << 20;
-        Some(instruction)
+    let data_path = match opt.data_obj.clone() {
+        Some(path) => path,
+        None => obj_path.with_extension("data.obj"),
+    };
+    // Check every output before any is written, so no output can overwrite
+    // the source.
+    for path in [
+        Some(&obj_path),
+        Some(&data_path),
+        opt.relocations_json.as_ref(),
+        opt.listing.as_ref(),
+        opt.line_map.as_ref(),
+    ]
+    .iter()
+    .flatten()
+    {
+        or_exit(check_output_path(&asm_path, path));
+    }
+    // The ISA a file declares applies unless flags say otherwise.
+    let declared = match declared_isa(asm) {
+        Ok(declared) => declared.unwrap_or_default(),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+    let isa = match &opt.isa_spec {
+        Some(path) => match parse_spec(&or_exit(read_text(path))) {
+            Ok(isa) => isa,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                process::exit(1);
+            }
+        },
+        None => Vec::new(),
+    };
+    let seed_labels = match &opt.seed_labels {
+        Some(path) => match parse_seed_labels(&or_exit(read_text(path))) {
+            Ok(seed_labels) => seed_labels,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                process::exit(1);
+            }
+        },
+        None => HashMap::new(),
+    };
+    let options = Options {
+        defines,
+        relax_branches: opt.relax_branches,
+        isa,
+        text_base: opt.text_base,
+        data_base: opt.data_base,
+        ignore_unknown_directives: opt.ignore_unknown_directives,
+        seed_labels,
+        extensions: if opt.extensions.is_empty() {
+            declared.extensions
+        } else {
+            opt.extensions.clone()
+        },
+        profile: opt.profile.or(declared.profile),
+        warn_zero_branches: opt.warn_zero_branches,
+        address_unit: opt.address_unit,
+        warn_unused_labels: opt.warn_unused_labels,
+        warn_null_stores: opt.warn_null_stores,
+        warn_x0_writes: opt.warn_x0_writes,
+    };
+    if opt.dump_ast {
+        if let Ok(statements) = parse_with(asm, &options) {
+            eprint!("{}", dump_ast(&statements));
+        }
+    }
+    if opt.dump_relocations {
+        if let Ok(relocations) = relocations(asm, &options) {
+            eprint!("{}", dump_relocations(&relocations));
+        }
+    }
+    if let Some(path) = &opt.relocations_json {
+        if let Ok(relocations) = pending_relocations(asm, &options) {
+            or_exit(write_file(path, relocations_json(&relocations)));
+        }
+    }
+    if opt.stats {
+        if let Ok(statements) = parse_with(asm, &options) {
+            eprint!("{}", stats(&statements));
+        }
+    }
+    let program = if opt.no_resolve {
+        match assemble_unresolved(asm, &options) {
+            Ok(program) => program,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                process::exit(1);
+            }
+        }
     } else {
-        None
+        match assemble_program_located(asm, &options) {
+            Ok(program) => program,
+            Err(err) => {
+                eprintln!("Error: {}", source.describe(&err, opt.offset_format));
+                process::exit(1);
+            }
+        }
+    };
+    if opt.verbose && program.instructions.is_empty() {
+        eprintln!("Note: no instructions were produced");
+    }
+    if let Some(path) = &opt.listing {
+        // Without `--no-resolve`, the program assembled, so its listing does
+        // too.
+        or_exit(write_file(path, or_exit(listing(asm, &options))));
+    }
+    if let Some(path) = &opt.line_map {
+        let lines = or_exit(line_addresses(asm, &options));
+        or_exit(write_file(path, source.line_map(&lines)));
+    }
+    if opt.xref {
+        print!("{}", xref(&or_exit(cross_references(asm, &options))));
+    }
+    if opt.symbols {
+        print!("{}", symbol_table(&program));
+    }
+    if opt.instruction_trace {
+        eprint!("{}", instruction_trace(&program.instructions));
+    }
+    let mut warnings = Warnings::default();
+    for warning in &program.warnings {
+        warnings.warn(warning);
+    }
+    let mut instructions = program.instructions;
+
+    if opt.trim_trailing_nops {
+        let targets = or_exit(branch_targets(asm, &options));
+        trim_trailing_nops(&mut instructions, &targets);
+    }
+    if let Some(size) = opt.padding {
+        pad(&mut instructions, size * WORD_SIZE, &mut warnings);
+    }
+    if let Some(alignment) = opt.pad_alignment {
+        if alignment == 0 {
+            eprintln!("Error: Invalid Alignment: `0`");
+            process::exit(1);
+        }
+        pad_to_alignment(&mut instructions, alignment);
+    }
+
+    let code = warnings.exit_code(opt.werror);
+    if code != 0 {
+        eprintln!(
+            "Error: {} warning(s) treated as errors (--werror)",
+            warnings.count
+        );
+        process::exit(code);
+    }
+
+    let mut outputs = vec![(obj_path.clone(), instructions)];
+    if !program.data.is_empty() {
+        outputs.push((data_path, program.data));
+    }
+    for (i, (path, mut words)) in outputs.into_iter().enumerate() {
+        // Only the serialized order changes: labels were resolved against
+        // the original layout, so offsets in the reversed image still
+        // count from each word's unreversed position.
+        if opt.reverse_output {
+            words.reverse();
+        }
+        let mut obj = emit(&words, opt.format, &opt.array_name, opt.output_width);
+        if let (0, Some(size)) = (i, opt.rom_size) {
+            if opt.format != OutputFormat::Binary {
+                eprintln!("Error: `--rom-size` needs `--format binary`");
+                process::exit(1);
+            }
+            if let Err(err) = fill_rom(&mut obj, size, opt.fill_byte) {
+                eprintln!("Error: {}", err);
+                process::exit(1);
+            }
+        }
+        if let Err(err) = write_atomic(&path, |file| file.write_all(&obj)) {
+            eprintln!("Error: cannot write `{}`: {}", path.display(), err);
+            process::exit(1);
+        }
     }
 }
 
-fn parse_add(line: &str) -> Option<u32> {
-    if let Some(caps) = ADD_REGEX.captures(line) {
-        let rd: u32 = caps[1].parse().unwrap();
-        let rs1: u32 = caps[2].parse().unwrap();
-        let rs2: u32 = caps[3].parse().unwrap();
-        let mut instruction: u32 = 0;
-        instruction |= 0b0110011;
-        instruction |= rd << 7;
-        instruction |= rs1 << 15;
-        instruction |= rs2 << 20;
-        Some(instruction)
-    } else {
-        None
-    }
-}
-
-fn parse_sub(line: &str) -> Option<u32> {
-    if let Some(caps) = SUB_REGEX.captures(line) {
-        let rd: u32 = caps[1].parse().unwrap();
-        let rs1: u32 = caps[2].parse().unwrap();
-        let rs2: u32 = caps[3].parse().unwrap();
-        let mut instruction: u32 = 0;
-        instruction |= 0b0110011;
-        instruction |= rd << 7;
-        instruction |= rs1 << 15;
-        instruction |= rs2 << 20;
-        instruction |= 1u32 << 30;
-        Some(instruction)
+/// The object file for `asm` when `--obj` is not given: `asm` with
+/// `extension`, or the name `extension` gives if it is a `{stem}` template.
+fn default_obj_path(asm: &Path, extension: &str) -> PathBuf {
+    if extension.contains("{stem}") {
+        let stem = asm.file_stem().unwrap_or_default().to_string_lossy();
+        asm.with_file_name(extension.replace("{stem}", &stem))
     } else {
-        None
+        asm.with_extension(extension)
     }
 }
 
-fn parse_beq(line: &str) -> Option<(u32, String)> {
-    if let Some(caps) = BEQ_REGEX.captures(line) {
-        let rs1: u32 = caps[1].parse().unwrap();
-        let rs2: u32 = caps[2].parse().unwrap();
-        let label: String = caps[3].to_string();
-        let mut instruction: u32 = 0;
-        instruction |= 0b1100011;
-        instruction |= rs1 << 15;
-        instruction |= rs2 << 20;
-        Some((instruction, label))
-    } else {
-        None
+/// Compare disassembled lines with the expected assembly, ignoring
+/// surrounding whitespace, and describe the first line that differs.
+fn check_expected(lines: &[String], expected: &str) -> Result<(), String> {
+    let expected: Vec<&str> = expected.lines().map(str::trim).collect();
+    for i in 0..lines.len().max(expected.len()) {
+        let found = lines.get(i).map(|line| line.trim());
+        let wanted = expected.get(i).copied();
+        if found != wanted {
+            let show = |line: Option<&str>| match line {
+                Some(line) => format!("`{}`", line),
+                None => "the end of the file".to_string(),
+            };
+            return Err(format!(
+                "Mismatch on line {}: expected {}, found {}",
+                i + 1,
+                show(wanted),
+                show(found)
+            ));
+        }
     }
+    Ok(())
 }
 
-fn parse_blt(line: &str) -> Option<(u32, String)> {
-    if let Some(caps) = BLT_REGEX.captures(line) {
-        let rs1: u32 = caps[1].parse().unwrap();
-        let rs2: u32 = caps[2].parse().unwrap();
-        let label: String = caps[3].to_string();
-        let mut instruction: u32 = 0;
-        instruction |= 0b1100011;
-        instruction |= 0b100 << 12;
-        instruction |= rs1 << 15;
-        instruction |= rs2 << 20;
-        Some((instruction, label))
-    } else {
-        None
-    }
-}
-
-fn transform_labels(
-    instructions: Vec<(u32, Option<String>)>,
-    labels: HashMap<String, usize>,
-) -> Vec<u32> {
-    instructions
-        .into_iter()
-        .enumerate()
-        .map(|(i, (mut inst, label))| {
-            if let Some(label) = label {
-                if let Some(j) = labels.get(&label) {
-                    if i < *j {
-                        let imm: u32 = (j - i).try_into().unwrap();
-                        inst |= (imm & 0b00000000_00000000_00000000_00011111) << 7;
-                    } else {
-                        let imm: u32 = (i - j).try_into().unwrap();
-                        inst |= (imm & 0b00000000_00000000_00000000_00011111) << 7;
-                        inst |= 0b00000010_00000000_00000000_00000000;
-                    }
-                    inst
-                } else {
-                    panic!("Invalid Label: `{}`", &label);
-                }
+/// Parse a `--fill-byte`, in hex with or without a `0x` prefix.
+fn parse_fill_byte(byte: &str) -> Result<u8, String> {
+    let digits = byte.strip_prefix("0x").unwrap_or(byte);
+    u8::from_str_radix(digits, 16).map_err(|_| format!("Invalid Fill Byte: `{}`", byte))
+}
+
+/// Pad a binary image up to `size` bytes with `fill`.
+fn fill_rom(obj: &mut Vec<u8>, size: usize, fill: u8) -> Result<(), String> {
+    if obj.len() > size {
+        return Err(format!(
+            "the program is {} bytes, larger than the {}-byte ROM",
+            obj.len(),
+            size
+        ));
+    }
+    obj.resize(size, fill);
+    Ok(())
+}
+
+/// Parse an `--output-width`, which must fit a whole instruction word.
+fn parse_output_width(width: &str) -> Result<usize, String> {
+    match width.parse() {
+        Ok(width) if width >= 32 => Ok(width),
+        _ => Err(format!(
+            "Invalid Output Width: `{}` (expected at least 32)",
+            width
+        )),
+    }
+}
+
+/// Parse a byte address given in decimal or as `0x` hex.
+fn parse_address(address: &str) -> Result<usize, String> {
+    let parsed = match address.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => address.parse(),
+    };
+    parsed.map_err(|_| format!("Invalid Address: `{}`", address))
+}
+
+/// Write `path` through a temporary file in the same directory that is renamed
+/// over it only once `write` succeeds, so a failed write leaves `path` as it
+/// was.
+fn write_atomic(path: &Path, write: impl FnOnce(&mut File) -> io::Result<()>) -> io::Result<()> {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".tmp");
+    let temp = path.with_file_name(name);
+    let result = File::create(&temp).and_then(|mut file| {
+        write(&mut file)?;
+        file.sync_all()
+    });
+    match result.and_then(|()| fs::rename(&temp, path)) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(&temp);
+            Err(err)
+        }
+    }
+}
+
+/// Parse `name = address` lines, skipping blank lines and `#` comments.
+fn parse_seed_labels(seeds: &str) -> Result<HashMap<String, usize>, String> {
+    let mut labels = HashMap::new();
+    for line in seeds.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (name, address) = line
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid Seed Label: `{}`", line))?;
+        labels.insert(name.trim().to_string(), parse_address(address.trim())?);
+    }
+    Ok(labels)
+}
+
+/// Parse `-D name[=value]` definitions. A bare name is defined as 1.
+fn parse_defines(defines: &[String]) -> Result<HashMap<String, i64>, String> {
+    let mut symbols = HashMap::new();
+    for define in defines {
+        let (name, value) = match define.split_once('=') {
+            Some((name, value)) => {
+                let value = value
+                    .parse()
+                    .map_err(|_| format!("Invalid Define: `{}`", define))?;
+                (name, value)
+            }
+            None => (define.as_str(), 1),
+        };
+        symbols.insert(name.to_string(), value);
+    }
+    Ok(symbols)
+}
+
+/// Refuse to write an output file over the source file.
+fn check_output_path(asm: &Path, obj: &Path) -> Result<(), String> {
+    let canonical = |path: &Path| match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) if !path.exists() => {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
             } else {
-                inst
+                dir
+            };
+            dir.canonicalize().map(|dir| dir.join(name))
+        }
+        _ => path.canonicalize(),
+    };
+    match (canonical(asm), canonical(obj)) {
+        (Ok(asm), Ok(obj)) if asm == obj => Err(format!(
+            "Invalid Output: `{}` is the input file",
+            obj.display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn dump_ast(statements: &[Statement]) -> String {
+    let mut dump = String::new();
+    for statement in statements {
+        let node = match statement {
+            Statement::Instruction(inst) => format!("Instruction {:?}", inst),
+            Statement::Pseudo(insts) => format!("Pseudo {:?}", insts),
+            Statement::Label(label) => format!("Label `{}`", label),
+            Statement::Org(offset) => format!("Directive .org {:#x}", offset),
+            Statement::Skip(count) => format!("Directive .skip {}", count),
+            Statement::Bytes(bytes) => format!("Bytes {:?}", bytes),
+            Statement::Global(label) => format!("Directive .globl `{}`", label),
+            Statement::Type(label, kind) => format!("Directive .type `{}` {:?}", label, kind),
+            Statement::Size(label, SymbolSize::Bytes(bytes)) => {
+                format!("Directive .size `{}` {}", label, bytes)
+            }
+            Statement::Size(label, SymbolSize::Since(since)) => {
+                format!("Directive .size `{}` .-{}", label, since)
             }
+            Statement::Section(Section::Text) => "Directive .text".to_string(),
+            Statement::Section(Section::Data) => "Directive .data".to_string(),
+            Statement::UnknownDirective(directive) => format!("Directive {} (ignored)", directive),
+        };
+        dump.push_str(&node);
+        dump.push('\n');
+    }
+    dump
+}
+
+/// Count instructions per mnemonic and per encoding format, expanding
+/// pseudo-instructions.
+fn stats(statements: &[Statement]) -> String {
+    let mut mnemonics = BTreeMap::new();
+    let mut formats = BTreeMap::new();
+    for statement in statements {
+        let insts = match statement {
+            Statement::Instruction(inst) => std::slice::from_ref(inst),
+            Statement::Pseudo(insts) => insts.as_slice(),
+            _ => &[],
+        };
+        for inst in insts {
+            *mnemonics.entry(inst.mnemonic()).or_insert(0) += 1;
+            *formats.entry(inst.format()).or_insert(0) += 1;
+        }
+    }
+    let mut table = String::new();
+    for (mnemonic, count) in mnemonics {
+        table.push_str(&format!("{:<8} {}\n", mnemonic, count));
+    }
+    for (format, count) in formats {
+        table.push_str(&format!("{}-type   {}\n", format, count));
+    }
+    table
+}
+
+fn symbol_table(program: &Program) -> String {
+    let mut table = String::new();
+    for (label, symbol) in &program.symbols {
+        let binding = if symbol.global { "global" } else { "local" };
+        table.push_str(&format!("{:#010x} {} {}\n", symbol.address, binding, label));
+    }
+    table
+}
+
+/// List each label followed by the addresses that refer to it.
+fn xref(references: &BTreeMap<String, Vec<usize>>) -> String {
+    let mut table = String::new();
+    for (label, addresses) in references {
+        table.push_str(label);
+        for address in addresses {
+            table.push_str(&format!(" {:#010x}", address));
+        }
+        table.push('\n');
+    }
+    table
+}
+
+/// List each pending relocation as its instruction index, kind and label.
+fn dump_relocations(relocations: &[(usize, Relocation)]) -> String {
+    let mut dump = String::new();
+    for (i, relocation) in relocations {
+        let kind = match relocation {
+            Relocation::Branch(_) => "branch",
+            Relocation::Jump(_) => "jump",
+            Relocation::PcrelHi(_) => "pcrel_hi",
+            Relocation::PcrelLo(_) => "pcrel_lo",
+            Relocation::PcrelPairLo(_) => "pcrel_pair_lo",
+            Relocation::Hi(_) => "hi",
+            Relocation::Lo(_) => "lo",
+        };
+        dump.push_str(&format!("{:>4} {:8} {}\n", i, kind, relocation.label()));
+    }
+    dump
+}
+
+/// List each pending relocation as a JSON object with its section, the byte
+/// offset of its word in that section, its ELF relocation type and its
+/// symbol. The low half of a PC-relative pair refers to its `auipc` by
+/// offset instead, as its ELF symbol is the `auipc`'s location. Labels are
+/// words, so they need no escaping.
+fn relocations_json(relocations: &[PendingRelocation]) -> String {
+    let records: Vec<String> = relocations
+        .iter()
+        .map(|pending| {
+            // Stores split their immediate, so their low parts differ.
+            let store = matches!(pending.word & 0b1111111, 0b0100011 | 0b0100111);
+            let kind = match (&pending.relocation, store) {
+                (Relocation::Branch(_), _) => "R_RISCV_BRANCH",
+                (Relocation::Jump(_), _) => "R_RISCV_JAL",
+                (Relocation::PcrelHi(_), _) => "R_RISCV_PCREL_HI20",
+                (Relocation::PcrelLo(_) | Relocation::PcrelPairLo(_), false) => {
+                    "R_RISCV_PCREL_LO12_I"
+                }
+                (Relocation::PcrelLo(_) | Relocation::PcrelPairLo(_), true) => {
+                    "R_RISCV_PCREL_LO12_S"
+                }
+                (Relocation::Hi(_), _) => "R_RISCV_HI20",
+                (Relocation::Lo(_), false) => "R_RISCV_LO12_I",
+                (Relocation::Lo(_), true) => "R_RISCV_LO12_S",
+            };
+            let section = match pending.section {
+                Section::Text => "text",
+                Section::Data => "data",
+            };
+            let target = match pending.pair {
+                Some(auipc) => format!("\"auipc\": {}", auipc),
+                None => format!("\"symbol\": \"{}\"", pending.relocation.label()),
+            };
+            format!(
+                "  {{ \"section\": \"{}\", \"offset\": {}, \"kind\": \"{}\", {} }}",
+                section, pending.offset, kind, target
+            )
         })
-        .collect()
+        .collect();
+    if records.is_empty() {
+        "[]\n".to_string()
+    } else {
+        format!("[\n{}\n]\n", records.join(",\n"))
+    }
+}
+
+/// Break each instruction word down into the fields of its encoding format.
+fn instruction_trace(instructions: &[u32]) -> String {
+    let mut trace = String::new();
+    for (i, inst) in instructions.iter().enumerate() {
+        let field = |shift: u32, bits: u32| (inst >> shift) & ((1 << bits) - 1);
+        let opcode = format!("opcode={:07b}", field(0, 7));
+        let funct3 = format!("funct3={:03b}", field(12, 3));
+        let funct7 = format!("funct7={:07b}", field(25, 7));
+        let rd = format!("rd={}", field(7, 5));
+        let rs1 = format!("rs1={}", field(15, 5));
+        let rs2 = format!("rs2={}", field(20, 5));
+        let fields = match field(0, 7) {
+            0b0110011 => vec![opcode, funct3, rd, rs1, rs2, funct7],
+            0b0000011 | 0b0010011 => {
+                let imm = format!("imm={}", *inst as i32 >> 20);
+                vec![opcode, funct3, rd, rs1, imm]
+            }
+            0b0100011 => {
+                let imm = ((*inst as i32 >> 25) << 5) | field(7, 5) as i32;
+                vec![opcode, funct3, rs1, rs2, format!("imm={}", imm)]
+            }
+            0b1100011 => {
+                let offset = format!("offset={}", field(7, 5));
+                let sign = format!("sign={}", field(25, 1));
+                vec![opcode, funct3, rs1, rs2, offset, sign]
+            }
+            0b0110111 | 0b0010111 => vec![opcode, rd, format!("imm={:#x}", inst >> 12)],
+            _ => vec![opcode],
+        };
+        trace.push_str(&format!("{:#010x}: {}\n", i * WORD_SIZE, fields.join(" ")));
+    }
+    trace
+}
+
+/// Zero-fill `instructions` up to `bytes` bytes.
+fn pad(instructions: &mut Vec<u32>, bytes: usize, warnings: &mut Warnings) {
+    if instructions.len() * WORD_SIZE > bytes {
+        warnings.warn("the padding length is smaller than the number of instructions.");
+    }
+    // Grow in one step, since `bytes` may be a large image.
+    let len = instructions.len().max(bytes.div_ceil(WORD_SIZE));
+    instructions.resize(len, 0);
+}
+
+/// Remove trailing `nop`s, keeping any at the indices in `targets` and every
+/// word before them.
+fn trim_trailing_nops(instructions: &mut Vec<u32>, targets: &HashSet<usize>) {
+    while let Some(inst) = instructions.last() {
+        let last = instructions.len() - 1;
+        if !matches!(inst, 0 | 0x00000013) || targets.contains(&last) {
+            break;
+        }
+        instructions.pop();
+    }
+}
+
+/// Zero-fill `instructions` up to the next multiple of `alignment` words.
+fn pad_to_alignment(instructions: &mut Vec<u32>, alignment: usize) {
+    let len = instructions.len().next_multiple_of(alignment);
+    instructions.resize(len, 0);
+}
+
+/// Assembly source with every include expanded.
+#[derive(Debug, Default)]
+struct Source {
+    text: String,
+    /// The file and 1-based line each line of `text` came from.
+    locations: Vec<(PathBuf, usize)>,
+}
+
+impl Source {
+    /// Write 1-based lines of `text` and their addresses as the file and
+    /// line each came from.
+    fn line_map(&self, lines: &[(usize, usize)]) -> String {
+        let mut map = String::new();
+        for (line, address) in lines {
+            if let Some((file, line)) = self.locations.get(line - 1) {
+                map.push_str(&format!("{}:{} {:#010x}\n", file.display(), line, address));
+            }
+        }
+        map
+    }
+
+    /// Describe `err`, prefixed with the file and line it arose on.
+    fn describe(&self, err: &LocatedError, offsets: OffsetFormat) -> String {
+        let location = err
+            .span
+            .and_then(|span| Some((self.locations.get(span.line)?, span.col)));
+        match location {
+            Some(((file, line), col)) => format!(
+                "{}:{}:{}: {}",
+                file.display(),
+                line,
+                col + 1,
+                err.error.display(offsets)
+            ),
+            None => err.error.display(offsets).to_string(),
+        }
+    }
+}
+
+/// Unwrap `result`, or report its error and exit like the other
+/// diagnostics.
+fn or_exit<T, E: std::fmt::Display>(result: Result<T, E>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|err| format!("cannot read `{}`: {}", path.display(), err))
+}
+
+fn read_text(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|err| format!("cannot read `{}`: {}", path.display(), err))
+}
+
+fn write_file(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), String> {
+    fs::write(path, contents).map_err(|err| format!("cannot write `{}`: {}", path.display(), err))
+}
+
+fn canonical(path: &Path) -> Result<PathBuf, String> {
+    path.canonicalize()
+        .map_err(|err| format!("cannot read `{}`: {}", path.display(), err))
+}
+
+/// Read the file at `path`, expanding its includes. `defines` are the
+/// symbols predefined for `.ifdef`, so includes in blocks not taken are left
+/// unexpanded.
+fn read_source(
+    path: &Path,
+    include_paths: &[PathBuf],
+    encoding: InputEncoding,
+    included: &mut HashSet<PathBuf>,
+    defines: &HashMap<String, i64>,
+) -> Result<Source, String> {
+    let mut source = Source::default();
+    let mut including = Vec::new();
+    let mut conditionals = Conditionals::new(defines.clone());
+    read_into(
+        &mut source,
+        path,
+        include_paths,
+        encoding,
+        included,
+        &mut including,
+        &mut conditionals,
+    )?;
+    Ok(source)
+}
+
+/// Append the file at `path` to `source`, expanding its includes.
+/// `including` holds the files whose includes are being expanded, so a
+/// cycle is reported rather than followed, and `conditionals` follows the
+/// `.ifdef` blocks open across files.
+fn read_into(
+    source: &mut Source,
+    path: &Path,
+    include_paths: &[PathBuf],
+    encoding: InputEncoding,
+    included: &mut HashSet<PathBuf>,
+    including: &mut Vec<PathBuf>,
+    conditionals: &mut Conditionals,
+) -> Result<(), String> {
+    let canonical_path = canonical(path)?;
+    included.insert(canonical_path.clone());
+    including.push(canonical_path);
+    let asm = decode_source(path, &read_file(path)?, encoding)?;
+    // Find `file` beside the including file, or else on the include path.
+    let find = |file: &Path, n: usize| {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        std::iter::once(dir)
+            .chain(include_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(file))
+            .find(|path| path.is_file())
+            .ok_or_else(|| {
+                format!(
+                    "{}:{}: Invalid Include: `{}`",
+                    path.display(),
+                    n + 1,
+                    file.display()
+                )
+            })
+    };
+    for (n, line) in asm.lines().enumerate() {
+        // Lines in blocks not taken are kept for the assembler to skip.
+        // Unbalanced conditionals are left for it to report, too.
+        if !conditionals.assembles(line).unwrap_or(true) {
+            source.text.push_str(line);
+            source.text.push('\n');
+            source.locations.push((path.to_path_buf(), n + 1));
+            continue;
+        }
+        if let Some(caps) = INCLUDE_REGEX.captures(line) {
+            let include = find(Path::new(&caps[2]), n)?;
+            let once = caps.get(1).is_some();
+            let canonical_include = canonical(&include)?;
+            if once && included.contains(&canonical_include) {
+                continue;
+            }
+            if including.contains(&canonical_include) {
+                return Err(format!(
+                    "{}:{}: Invalid Include: `{}` is already being included",
+                    path.display(),
+                    n + 1,
+                    &caps[2]
+                ));
+            }
+            read_into(
+                source,
+                &include,
+                include_paths,
+                encoding,
+                included,
+                including,
+                conditionals,
+            )?;
+            continue;
+        }
+        if let Some(caps) = INCBIN_REGEX.captures(line) {
+            // The bytes become a single `.byte` line, packed with any data
+            // around them.
+            let bytes = read_file(&find(Path::new(&caps[1]), n)?)?;
+            if !bytes.is_empty() {
+                let bytes: Vec<String> =
+                    bytes.iter().map(|byte| format!("{:#04x}", byte)).collect();
+                source.text.push_str(".byte ");
+                source.text.push_str(&bytes.join(", "));
+            }
+        } else {
+            source.text.push_str(line);
+        }
+        source.text.push('\n');
+        source.locations.push((path.to_path_buf(), n + 1));
+    }
+    including.pop();
+    Ok(())
+}
+
+/// Decode the bytes of the source file at `path`.
+fn decode_source(path: &Path, bytes: &[u8], encoding: InputEncoding) -> Result<String, String> {
+    match encoding {
+        InputEncoding::Utf8 => String::from_utf8(bytes.to_vec()).map_err(|_| {
+            format!(
+                "Invalid Encoding: `{}` is not valid UTF-8 (try `--input-encoding latin1`)",
+                path.display()
+            )
+        }),
+        // Latin-1 maps each byte to the code point of the same value.
+        InputEncoding::Latin1 => Ok(bytes.iter().map(|byte| *byte as char).collect()),
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)]
 mod tests {
     use super::*;
+    use risc_v_assembler::{assemble, assemble_program, assemble_program_with, parse};
+
+    #[test]
+    fn padding() {
+        let mut instructions = assemble("add x5, x6, x7\nnop").unwrap();
+        pad(&mut instructions, 8 * WORD_SIZE, &mut Warnings::default());
+        let obj = emit(&instructions, OutputFormat::Binary, "PROGRAM", 32);
+        assert_eq!(obj.len(), 32);
+        assert_eq!(obj[..4], [0b10110011, 0b00000010, 0b01110011, 0]);
+        assert!(obj[4..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn pad_large() {
+        let mut instructions = assemble("nop").unwrap();
+        let bytes = 12 << 20 | 2;
+        pad(&mut instructions, bytes, &mut Warnings::default());
+        assert_eq!(instructions.len(), (3 << 20) + 1);
+        // A single allocation rather than repeated doubling.
+        assert!(instructions.capacity() - instructions.len() < instructions.len() / 8);
+    }
+
+    #[test]
+    fn trim_nops() {
+        let asm = "bne x5, x0, Done\nadd x5, x6, x7\nDone:\nnop\naddi x0, x0, 0\nnop";
+        let options = Options::default();
+        let mut instructions = assemble(asm).unwrap();
+        trim_trailing_nops(&mut instructions, &branch_targets(asm, &options).unwrap());
+        assert_eq!(instructions, assemble(asm).unwrap()[..3]);
+        let mut instructions = assemble(asm).unwrap();
+        trim_trailing_nops(&mut instructions, &HashSet::new());
+        assert_eq!(instructions, assemble(asm).unwrap()[..2]);
+    }
+
+    #[test]
+    fn pad_alignment() {
+        let mut instructions = assemble("nop\nnop\nnop\nnop\nnop").unwrap();
+        pad_to_alignment(&mut instructions, 4);
+        assert_eq!(instructions.len(), 8);
+        pad_to_alignment(&mut instructions, 4);
+        assert_eq!(instructions.len(), 8);
+        let mut instructions = assemble("nop").unwrap();
+        pad(&mut instructions, 5 * WORD_SIZE, &mut Warnings::default());
+        pad_to_alignment(&mut instructions, 4);
+        assert_eq!(instructions.len(), 8);
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("risc-v-assembler-{}", name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn include_path() {
+        let dir = temp_dir("include-path");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib/add.asm"), "add x5, x6, x7\n").unwrap();
+        fs::write(dir.join("main.asm"), ".include \"add.asm\"\nnop\n").unwrap();
+        let asm = read_source(
+            &dir.join("main.asm"),
+            &[dir.join("lib")],
+            InputEncoding::Utf8,
+            &mut HashSet::new(),
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            assemble(&asm.text).unwrap(),
+            [0b0000000_00111_00110_000_00101_0110011, 0]
+        );
+    }
+
+    #[test]
+    fn incbin() {
+        let dir = temp_dir("incbin");
+        fs::create_dir_all(dir.join("blobs")).unwrap();
+        fs::write(
+            dir.join("blobs/blob.bin"),
+            [0x01, 0x02, 0x03, 0x04, 0xff, 0x80],
+        )
+        .unwrap();
+        let main = dir.join("main.asm");
+        fs::write(&main, ".incbin \"blobs/blob.bin\"\nAfter:\nnop\n").unwrap();
+        let source = read_source(
+            &main,
+            &[],
+            InputEncoding::Utf8,
+            &mut HashSet::new(),
+            &HashMap::new(),
+        )
+        .unwrap();
+        let program = assemble_program_located(&source.text, &Options::default()).unwrap();
+        assert_eq!(program.instructions, [0x04030201, 0x000080ff, 0]);
+        assert_eq!(program.symbols["After"].address, 8);
+        fs::write(&main, ".incbin \"missing.bin\"\n").unwrap();
+        assert!(read_source(
+            &main,
+            &[],
+            InputEncoding::Utf8,
+            &mut HashSet::new(),
+            &HashMap::new()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn conditional_include() {
+        let dir = temp_dir("conditional-include");
+        let main = dir.join("main.asm");
+        fs::write(
+            &main,
+            ".ifdef DEBUG\n.include \"missing.asm\"\n.incbin \"missing.bin\"\n.else\nnop\n.endif\n",
+        )
+        .unwrap();
+        let read = |defines: &HashMap<String, i64>| {
+            read_source(
+                &main,
+                &[],
+                InputEncoding::Utf8,
+                &mut HashSet::new(),
+                defines,
+            )
+        };
+        let source = read(&HashMap::new()).unwrap();
+        assert_eq!(assemble(&source.text).unwrap(), [0]);
+        let defines = HashMap::from([("DEBUG".to_string(), 1)]);
+        assert!(read(&defines)
+            .unwrap_err()
+            .contains("Invalid Include: `missing.asm`"));
+        // `.equ` defines symbols for later blocks.
+        fs::write(
+            &main,
+            ".equ DEBUG, 1\n.ifndef DEBUG\n.include \"missing.asm\"\n.endif\n",
+        )
+        .unwrap();
+        assert!(read(&HashMap::new()).is_ok());
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = temp_dir("include-cycle");
+        let main = dir.join("main.asm");
+        fs::write(&main, ".include \"main.asm\"\n").unwrap();
+        let err = read_source(
+            &main,
+            &[],
+            InputEncoding::Utf8,
+            &mut HashSet::new(),
+            &HashMap::new(),
+        )
+        .unwrap_err();
+        assert!(err.ends_with(":1: Invalid Include: `main.asm` is already being included"));
+        fs::write(&main, "nop\n.include \"a.asm\"\n").unwrap();
+        fs::write(dir.join("a.asm"), ".include \"main.asm\"\n").unwrap();
+        let err = read_source(
+            &main,
+            &[],
+            InputEncoding::Utf8,
+            &mut HashSet::new(),
+            &HashMap::new(),
+        )
+        .unwrap_err();
+        assert!(err.ends_with("a.asm:1: Invalid Include: `main.asm` is already being included"));
+        // Including a file twice in a row is not a cycle.
+        fs::write(&main, ".include \"a.asm\"\n.include \"a.asm\"\n").unwrap();
+        fs::write(dir.join("a.asm"), "nop\n").unwrap();
+        let asm = read_source(
+            &main,
+            &[],
+            InputEncoding::Utf8,
+            &mut HashSet::new(),
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(assemble(&asm.text).unwrap(), [0, 0]);
+    }
+
+    #[test]
+    fn include_once() {
+        let dir = temp_dir("include-once");
+        fs::write(dir.join("add.asm"), "add x5, x6, x7\n").unwrap();
+        fs::write(
+            dir.join("main.asm"),
+            ".include \"add.asm\"\n.include_once \"add.asm\"\n.include_once \"main.asm\"\n",
+        )
+        .unwrap();
+        let asm = read_source(
+            &dir.join("main.asm"),
+            &[],
+            InputEncoding::Utf8,
+            &mut HashSet::new(),
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            assemble(&asm.text).unwrap(),
+            [0b0000000_00111_00110_000_00101_0110011]
+        );
+    }
+
+    #[test]
+    fn include_location() {
+        let dir = temp_dir("include-location");
+        let main = dir.join("main.asm");
+        let lib = dir.join("lib.asm");
+        fs::write(&main, "nop\n.include \"lib.asm\"\n.error \"unreachable\"\n").unwrap();
+        let describe = || {
+            let source = read_source(
+                &main,
+                &[],
+                InputEncoding::Utf8,
+                &mut HashSet::new(),
+                &HashMap::new(),
+            )
+            .unwrap();
+            let err = assemble_program_located(&source.text, &Options::default()).unwrap_err();
+            source.describe(&err, OffsetFormat::Decimal)
+        };
+        fs::write(&lib, "nop\nadd x5, x6, y7\n").unwrap();
+        assert_eq!(
+            describe(),
+            format!("{}:2:1: Invalid Register: `y7`", lib.display())
+        );
+        fs::write(&lib, "nop\nadd x5, x6, x7\n").unwrap();
+        assert_eq!(describe(), format!("{}:3:1: unreachable", main.display()));
+    }
 
     #[test]
-    fn ld() {
-        let instruction = parse_ld("ld x25, 40(x6)").unwrap();
-        assert_eq!(instruction, 0b000000101000_00110_011_11001_0000011);
+    fn latin1() {
+        let dir = temp_dir("latin1");
+        let path = dir.join("main.asm");
+        // `// café` with a Latin-1 `é`.
+        fs::write(&path, b"add x5, x6, x7 // caf\xe9\n").unwrap();
+        let read =
+            |encoding| read_source(&path, &[], encoding, &mut HashSet::new(), &HashMap::new());
+        assert!(read(InputEncoding::Utf8)
+            .unwrap_err()
+            .contains("--input-encoding latin1"));
+        let asm = read(InputEncoding::Latin1).unwrap().text;
+        assert_eq!(asm, "add x5, x6, x7 // caf\u{e9}\n");
+        assert_eq!(
+            assemble(&asm).unwrap(),
+            [0b0000000_00111_00110_000_00101_0110011]
+        );
     }
 
     #[test]
-    fn sd() {
-        let instruction = parse_sd("sd x5, 40(x6)").unwrap();
-        assert_eq!(instruction, 0b0000001_00101_00110_011_01000_0100011);
+    fn werror() {
+        let mut instructions = assemble("nop\nnop").unwrap();
+        let mut warnings = Warnings::default();
+        pad(&mut instructions, 2 * WORD_SIZE, &mut warnings);
+        assert_eq!(warnings.exit_code(true), 0);
+        pad(&mut instructions, WORD_SIZE, &mut warnings);
+        assert_eq!(warnings.count, 1);
+        assert_eq!(warnings.exit_code(false), 0);
+        assert_ne!(warnings.exit_code(true), 0);
     }
 
     #[test]
-    fn and() {
-        let instruction = parse_and("and x5, x6, x7").unwrap();
-        assert_eq!(instruction, 0b0000000_00111_00110_111_00101_0110011);
+    fn seed_labels() {
+        let seeds = "# MMIO\nUART = 0x10000000\n\nROM=4096\n";
+        let seed_labels = parse_seed_labels(seeds).unwrap();
+        assert_eq!(seed_labels["UART"], 0x1000_0000);
+        assert_eq!(seed_labels["ROM"], 4096);
+        assert!(parse_seed_labels("UART 0x10000000").is_err());
+        assert!(parse_seed_labels("UART = high").is_err());
+        let options = Options {
+            seed_labels,
+            ..Options::default()
+        };
+        let asm = "lui x5, %hi(UART)\naddi x5, x5, %lo(UART)";
+        let program = assemble_program_with(asm, &options).unwrap();
+        // Expected words from `llvm-mc -triple=riscv64 -show-encoding` for
+        // `lui t0, 0x10000` and `addi t0, t0, 0`.
+        assert_eq!(program.instructions, [0x100002b7, 0x00028293]);
     }
 
     #[test]
-    fn or() {
-        let instruction = parse_or("or x5, x6, x7").unwrap();
-        assert_eq!(instruction, 0b0000000_00111_00110_110_00101_0110011);
+    fn rom_image() {
+        let instructions = assemble("add x5, x6, x7\nnop").unwrap();
+        let mut obj = emit(&instructions, OutputFormat::Binary, "PROGRAM", 32);
+        fill_rom(&mut obj, 64, parse_fill_byte("0xff").unwrap()).unwrap();
+        assert_eq!(obj.len(), 64);
+        assert_eq!(obj[..8], [0xb3, 0x02, 0x73, 0x00, 0, 0, 0, 0]);
+        assert!(obj[8..].iter().all(|byte| *byte == 0xff));
+        assert!(fill_rom(&mut obj, 32, 0xff).is_err());
+        assert_eq!(parse_fill_byte("a5"), Ok(0xa5));
+        assert!(parse_fill_byte("0x100").is_err());
     }
 
     #[test]
-    fn add() {
-        let instruction = parse_add("add x5, x6, x7").unwrap();
-        assert_eq!(instruction, 0b0000000_00111_00110_000_00101_0110011);
+    fn obj_extension() {
+        let asm = Path::new("src/prog.asm");
+        assert_eq!(default_obj_path(asm, "obj"), Path::new("src/prog.obj"));
+        assert_eq!(default_obj_path(asm, "mem"), Path::new("src/prog.mem"));
+        assert_eq!(
+            default_obj_path(asm, "{stem}-rom.bin"),
+            Path::new("src/prog-rom.bin")
+        );
     }
 
     #[test]
-    fn sub() {
-        let instruction = parse_sub("sub x5, x6, x7").unwrap();
-        assert_eq!(instruction, 0b0100000_00111_00110_000_00101_0110011);
+    fn expect() {
+        let dir = temp_dir("expect");
+        let lines = disassemble::disassemble(
+            &assemble("add x5, x6, x7\nor x5, x5, x6").unwrap(),
+            RegNames::Numeric,
+        );
+        fs::write(dir.join("match.s"), format!("{}\n", lines.join("\n"))).unwrap();
+        fs::write(dir.join("mismatch.s"), format!("{}\nnop\n", lines[0])).unwrap();
+        let check =
+            |name: &str| check_expected(&lines, &fs::read_to_string(dir.join(name)).unwrap());
+        assert_eq!(check("match.s"), Ok(()));
+        assert_eq!(
+            check("mismatch.s"),
+            Err(format!(
+                "Mismatch on line 2: expected `nop`, found `{}`",
+                lines[1]
+            ))
+        );
+        assert_eq!(
+            check_expected(&lines[..1], &lines.join("\n")),
+            Err(format!(
+                "Mismatch on line 2: expected `{}`, found the end of the file",
+                lines[1]
+            ))
+        );
     }
 
     #[test]
-    fn beq() {
-        let (inst, label) = parse_beq("beq x5, x6, Label").unwrap();
-        let instructions = vec![(inst, Some(label))];
-        let mut labels = HashMap::new();
-        labels.insert("Label".to_string(), 2);
-        let instructions = transform_labels(instructions, labels);
-        assert_eq!(instructions[0], 0b0000000_00110_00101_000_00010_1100011);
+    fn output_width() {
+        let instructions = assemble("add x5, x6, x7").unwrap();
+        let obj = emit(&instructions, OutputFormat::Text, "PROGRAM", 40);
+        assert_eq!(
+            String::from_utf8(obj).unwrap(),
+            format!("{}{}\n", "0".repeat(8), "00000000011100110000001010110011")
+        );
+        assert_eq!(parse_output_width("40"), Ok(40));
+        assert!(parse_output_width("16").is_err());
+        assert!(parse_output_width("wide").is_err());
+    }
+
+    #[test]
+    fn grouped() {
+        let instructions = assemble("add x5, x6, x7\nld x5, 40(x6)").unwrap();
+        let obj = emit(&instructions, OutputFormat::GroupedBinary, "PROGRAM", 32);
+        assert_eq!(
+            String::from_utf8(obj).unwrap(),
+            "0000000_00111_00110_000_00101_0110011\n000000101000_00110_011_00101_0000011\n"
+        );
+    }
+
+    #[test]
+    fn rust_array() {
+        pub const BOOT: [u32; 2] = [0x007302b3, 0x00000000];
+        let instructions = assemble("add x5, x6, x7\nnop").unwrap();
+        let obj = emit(&instructions, OutputFormat::RustArray, "BOOT", 32);
+        assert_eq!(
+            String::from_utf8(obj).unwrap(),
+            "pub const BOOT: [u32; 2] = [0x007302b3, 0x00000000];\n"
+        );
+        assert_eq!(instructions, BOOT);
+    }
+
+    #[test]
+    fn coe() {
+        let instructions = assemble("add x5, x6, x7\nnop").unwrap();
+        let obj = emit(&instructions, OutputFormat::Coe, "PROGRAM", 32);
+        assert_eq!(
+            String::from_utf8(obj).unwrap(),
+            "memory_initialization_radix=16;\nmemory_initialization_vector=\n007302b3,\n00000000;\n"
+        );
+    }
+
+    #[test]
+    fn ast() {
+        let statements = parse("Loop:\nadd x5, x6, x7\nbeq x5, x6, Loop\n.org 0x10").unwrap();
+        let dump = dump_ast(&statements);
+        let nodes: Vec<&str> = dump.lines().collect();
+        assert_eq!(nodes[0], "Label `Loop`");
+        assert_eq!(nodes[1], "Instruction R { op: Add, rd: 5, rs1: 6, rs2: 7 }");
+        assert_eq!(
+            nodes[2],
+            "Instruction Branch { op: Beq, rs1: 5, rs2: 6, label: \"Loop\" }"
+        );
+        assert_eq!(nodes[3], "Directive .org 0x10");
+    }
+
+    #[test]
+    fn relocation_dump() {
+        let asm = "Loop:\nbeq x5, x6, Exit\nblt x5, x6, Loop\nj Loop\nExit:\nnop";
+        let relocations = relocations(asm, &Options::default()).unwrap();
+        assert_eq!(
+            dump_relocations(&relocations),
+            "   0 branch   Exit\n   1 branch   Loop\n   2 jump     Loop\n"
+        );
+    }
+
+    #[test]
+    fn xref_table() {
+        let asm = "Loop:\nbeq x5, x6, Loop\nj Loop\nUnused:";
+        let references = cross_references(asm, &Options::default()).unwrap();
+        assert_eq!(xref(&references), "Loop 0x00000000 0x00000004\nUnused\n");
+    }
+
+    #[test]
+    fn relocation_json() {
+        let asm = "nop\nLoop:\nbeq x5, x6, External\nj Loop";
+        let relocations = pending_relocations(asm, &Options::default()).unwrap();
+        assert_eq!(
+            relocations_json(&relocations),
+            "[\n  { \"section\": \"text\", \"offset\": 4, \"kind\": \"R_RISCV_BRANCH\", \"symbol\": \"External\" },\n  \
+             { \"section\": \"text\", \"offset\": 8, \"kind\": \"R_RISCV_JAL\", \"symbol\": \"Loop\" }\n]\n"
+        );
+        assert_eq!(relocations_json(&[]), "[]\n");
+    }
+
+    #[test]
+    fn relocation_json_types() {
+        let asm = "lui x6, %hi(Ext)\nsd x5, %lo(Ext)(x6)\nld x5, %lo(Ext)(x6)\n1:\nauipc x7, %pcrel_hi(Ext)\naddi x7, x7, %pcrel_lo(1b)\nlla x8, Ext\n.data\n.word 1\nj Ext";
+        let relocations = pending_relocations(asm, &Options::default()).unwrap();
+        let json = relocations_json(&relocations);
+        let lines: Vec<&str> = json.lines().collect();
+        assert_eq!(
+            lines[2],
+            "  { \"section\": \"text\", \"offset\": 4, \"kind\": \"R_RISCV_LO12_S\", \"symbol\": \"Ext\" },"
+        );
+        assert_eq!(
+            lines[3],
+            "  { \"section\": \"text\", \"offset\": 8, \"kind\": \"R_RISCV_LO12_I\", \"symbol\": \"Ext\" },"
+        );
+        // Each low half names the `auipc` it pairs with.
+        assert_eq!(
+            lines[5],
+            "  { \"section\": \"text\", \"offset\": 16, \"kind\": \"R_RISCV_PCREL_LO12_I\", \"auipc\": 12 },"
+        );
+        assert_eq!(
+            lines[7],
+            "  { \"section\": \"text\", \"offset\": 24, \"kind\": \"R_RISCV_PCREL_LO12_I\", \"auipc\": 20 },"
+        );
+        // Offsets count from the start of each section.
+        assert_eq!(
+            lines[8],
+            "  { \"section\": \"data\", \"offset\": 4, \"kind\": \"R_RISCV_JAL\", \"symbol\": \"Ext\" }"
+        );
+    }
+
+    #[test]
+    fn trace() {
+        let instructions = assemble("add x5, x6, x7\naddi x5, x6, -1").unwrap();
+        let trace = instruction_trace(&instructions);
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(
+            lines[0],
+            "0x00000000: opcode=0110011 funct3=000 rd=5 rs1=6 rs2=7 funct7=0000000"
+        );
+        assert_eq!(
+            lines[1],
+            "0x00000004: opcode=0010011 funct3=000 rd=5 rs1=6 imm=-1"
+        );
+    }
+
+    #[test]
+    fn defines() {
+        let defines = parse_defines(&["DEBUG".to_string(), "LEVEL=2".to_string()]).unwrap();
+        assert_eq!(defines["DEBUG"], 1);
+        assert_eq!(defines["LEVEL"], 2);
+        assert!(parse_defines(&["LEVEL=high".to_string()]).is_err());
+        let asm = ".ifdef DEBUG\nadd x5, x6, x7\n.else\nnop\n.endif";
+        let options = Options {
+            defines,
+            ..Options::default()
+        };
+        let program = assemble_program_with(asm, &options).unwrap();
+        assert_eq!(
+            program.instructions,
+            [0b0000000_00111_00110_000_00101_0110011]
+        );
+    }
+
+    #[test]
+    fn histogram() {
+        let asm = "Loop:\nadd x5, x6, x7\nsub x5, x6, x7\nadd x5, x5, x5\n\
+                   li x5, 0x12345678\nsd x5, 0(x6)\nbeq x5, x6, Loop";
+        let table = stats(&parse(asm).unwrap());
+        let rows: Vec<&str> = table.lines().collect();
+        assert_eq!(
+            rows,
+            [
+                "add      2",
+                "addi     1",
+                "beq      1",
+                "lui      1",
+                "sd       1",
+                "sub      1",
+                "B-type   1",
+                "I-type   1",
+                "R-type   3",
+                "S-type   1",
+                "U-type   1",
+            ]
+        );
+    }
+
+    #[test]
+    fn symbols() {
+        let program = assemble_program(".globl Main\nMain:\nnop\nLoop:\nnop").unwrap();
+        assert_eq!(
+            symbol_table(&program),
+            "0x00000004 local Loop\n0x00000000 global Main\n"
+        );
+    }
+
+    #[test]
+    fn atomic_write() {
+        let dir = temp_dir("atomic-write");
+        let obj = dir.join("main.obj");
+        fs::write(&obj, "old\n").unwrap();
+        let err = write_atomic(&obj, |file| {
+            file.write_all(b"partial")?;
+            Err(io::Error::other("disk full"))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "disk full");
+        assert_eq!(fs::read_to_string(&obj).unwrap(), "old\n");
+        assert!(!dir.join(".main.obj.tmp").exists());
+        write_atomic(&obj, |file| file.write_all(b"new\n")).unwrap();
+        assert_eq!(fs::read_to_string(&obj).unwrap(), "new\n");
+        assert!(!dir.join(".main.obj.tmp").exists());
     }
 
     #[test]
-    fn blt() {
-        let (inst, label) = parse_blt("blt x5, x6, Label").unwrap();
-        let instructions = vec![(0, None), (0, None), (inst, Some(label))];
-        let mut labels = HashMap::new();
-        labels.insert("Label".to_string(), 0);
-        let instructions = transform_labels(instructions, labels);
-        assert_eq!(instructions[2], 0b0000001_00110_00101_100_00010_1100011);
+    fn output_is_input() {
+        let dir = temp_dir("output-is-input");
+        let asm = dir.join("main.asm");
+        fs::write(&asm, "nop\n").unwrap();
+        assert!(check_output_path(
+            &asm,
+            &dir.join("../risc-v-assembler-output-is-input/main.asm")
+        )
+        .is_err());
+        assert!(check_output_path(&asm, &dir.join("main.obj")).is_ok());
+        assert_eq!(fs::read_to_string(&asm).unwrap(), "nop\n");
     }
 }
@@ -0,0 +1,110 @@
+//! Format assembled words for output.
+
+use std::str::FromStr;
+
+/// How assembled words are written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Binary,
+    RustArray,
+    GroupedBinary,
+    /// A Xilinx coefficient file for initializing block RAM.
+    Coe,
+}
+
+/// Every output format, by the name `--format` takes.
+const FORMATS: &[(&str, OutputFormat)] = &[
+    ("text", OutputFormat::Text),
+    ("binary", OutputFormat::Binary),
+    ("rust-array", OutputFormat::RustArray),
+    ("grouped-binary", OutputFormat::GroupedBinary),
+    ("coe", OutputFormat::Coe),
+];
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match FORMATS.iter().find(|(name, _)| *name == s) {
+            Some((_, format)) => Ok(*format),
+            None => {
+                let names: Vec<&str> = FORMATS.iter().map(|(name, _)| *name).collect();
+                Err(format!(
+                    "Invalid Format: `{}`, expected one of {}",
+                    s,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+/// Format `instructions`, writing text output `width` bits wide and naming
+/// a Rust array `array_name`.
+pub fn emit(instructions: &[u32], format: OutputFormat, array_name: &str, width: usize) -> Vec<u8> {
+    let mut obj = Vec::new();
+    match format {
+        OutputFormat::Text => {
+            for inst in instructions {
+                obj.extend(format!("{:0>width$b}\n", inst, width = width).bytes());
+            }
+        }
+        OutputFormat::Binary => {
+            obj.reserve(instructions.len() * 4);
+            for inst in instructions {
+                obj.extend_from_slice(&inst.to_le_bytes());
+            }
+        }
+        OutputFormat::GroupedBinary => {
+            for inst in instructions {
+                obj.extend(format!("{}\n", grouped_binary(*inst)).bytes());
+            }
+        }
+        OutputFormat::RustArray => {
+            let words: Vec<String> = instructions
+                .iter()
+                .map(|inst| format!("{:#010x}", inst))
+                .collect();
+            let array = format!(
+                "pub const {}: [u32; {}] = [{}];\n",
+                array_name,
+                instructions.len(),
+                words.join(", ")
+            );
+            obj.extend(array.bytes());
+        }
+        OutputFormat::Coe => {
+            let words: Vec<String> = instructions
+                .iter()
+                .map(|inst| format!("{:08x}", inst))
+                .collect();
+            let coe = format!(
+                "memory_initialization_radix=16;\nmemory_initialization_vector=\n{};\n",
+                words.join(",\n")
+            );
+            obj.extend(coe.bytes());
+        }
+    }
+    obj
+}
+
+/// Write `inst` in binary with `_` between the fields of its encoding
+/// format. Words with an unknown opcode are written ungrouped.
+fn grouped_binary(inst: u32) -> String {
+    let widths: &[usize] = match inst & 0b1111111 {
+        // R-, S- and B-type share field boundaries.
+        0b0110011 | 0b0100011 | 0b1100011 => &[7, 5, 5, 3, 5, 7],
+        0b0000011 | 0b0010011 => &[12, 5, 3, 5, 7],
+        0b0110111 | 0b0010111 | 0b1101111 => &[20, 5, 7],
+        _ => &[32],
+    };
+    let bits = format!("{:0>32b}", inst);
+    let mut fields = Vec::new();
+    let mut start = 0;
+    for width in widths {
+        fields.push(&bits[start..start + width]);
+        start += width;
+    }
+    fields.join("_")
+}
@@ -0,0 +1,271 @@
+use risc_v_assembler::output::OutputFormat;
+use risc_v_assembler::{assemble, assemble_to_bytes};
+use std::convert::TryInto;
+use std::fs;
+use std::process::Command;
+
+const PROGRAM: &str = "Loop:\naddi x5, x5, -1\nbne x5, x0, Loop\nsd x5, 8(x2)\n";
+
+#[test]
+fn bytes_match_cli() {
+    let dir = std::env::temp_dir().join("risc-v-assembler-cli");
+    fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("main.asm");
+    fs::write(&asm, PROGRAM).unwrap();
+    for (name, format) in [
+        ("text", OutputFormat::Text),
+        ("binary", OutputFormat::Binary),
+        ("rust-array", OutputFormat::RustArray),
+        ("grouped-binary", OutputFormat::GroupedBinary),
+        ("coe", OutputFormat::Coe),
+    ] {
+        let obj = dir.join(format!("{}.obj", name));
+        let status = Command::new(env!("CARGO_BIN_EXE_risc-v-assembler"))
+            .arg(&asm)
+            .arg("--obj")
+            .arg(&obj)
+            .args(["--format", name])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(
+            fs::read(&obj).unwrap(),
+            assemble_to_bytes(PROGRAM, format).unwrap()
+        );
+    }
+}
+
+#[test]
+fn reverse_output() {
+    let dir = std::env::temp_dir().join("risc-v-assembler-cli-reverse");
+    fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("main.asm");
+    fs::write(&asm, PROGRAM).unwrap();
+    let obj = dir.join("main.obj");
+    let status = Command::new(env!("CARGO_BIN_EXE_risc-v-assembler"))
+        .arg(&asm)
+        .arg("--obj")
+        .arg(&obj)
+        .args(["--format", "binary", "--reverse-output"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let words: Vec<u32> = fs::read(&obj)
+        .unwrap()
+        .chunks(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+    let mut expected = assemble(PROGRAM).unwrap();
+    expected.reverse();
+    assert_eq!(words, expected);
+}
+
+#[test]
+fn obj_extension() {
+    let dir = std::env::temp_dir().join("risc-v-assembler-cli-extension");
+    fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("prog.asm");
+    fs::write(&asm, PROGRAM).unwrap();
+    let _ = fs::remove_file(dir.join("prog.mem"));
+    let status = Command::new(env!("CARGO_BIN_EXE_risc-v-assembler"))
+        .arg(&asm)
+        .args(["--obj-extension", "mem"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        fs::read(dir.join("prog.mem")).unwrap(),
+        assemble_to_bytes(PROGRAM, OutputFormat::Text).unwrap()
+    );
+}
+
+#[test]
+fn empty_program() {
+    let dir = std::env::temp_dir().join("risc-v-assembler-cli-empty");
+    fs::create_dir_all(&dir).unwrap();
+    for (name, source) in [
+        ("comments", "// Nothing yet.\n\n"),
+        ("labels", "Start:\nEnd:\n"),
+    ] {
+        let asm = dir.join(format!("{}.asm", name));
+        fs::write(&asm, source).unwrap();
+        let obj = dir.join(format!("{}.obj", name));
+        let output = Command::new(env!("CARGO_BIN_EXE_risc-v-assembler"))
+            .arg(&asm)
+            .arg("--obj")
+            .arg(&obj)
+            .arg("--verbose")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("no instructions were produced"));
+        assert!(fs::read(&obj).unwrap().is_empty());
+    }
+}
+
+#[test]
+fn no_resolve() {
+    let dir = std::env::temp_dir().join("risc-v-assembler-cli-no-resolve");
+    fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("prog.asm");
+    fs::write(&asm, "nop\nbeq x5, x0, External\n").unwrap();
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_risc-v-assembler"))
+            .arg(&asm)
+            .arg("--obj")
+            .arg(dir.join("prog.obj"))
+            .arg("--relocations-json")
+            .arg(dir.join("prog.json"))
+            .args(["--format", "binary"])
+            .args(extra)
+            .status()
+            .unwrap()
+    };
+    assert!(!run(&[]).success());
+    assert!(run(&["--no-resolve"]).success());
+    // The branch keeps its registers and opcode, with its offset left zero.
+    let obj = fs::read(dir.join("prog.obj")).unwrap();
+    assert_eq!(obj[4..], 0x00028063u32.to_le_bytes());
+    let json = fs::read_to_string(dir.join("prog.json")).unwrap();
+    assert!(json.contains("\"offset\": 4, \"kind\": \"R_RISCV_BRANCH\", \"symbol\": \"External\""));
+}
+
+#[test]
+fn no_default_obj() {
+    let dir = std::env::temp_dir().join("risc-v-assembler-cli-no-default");
+    fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("prog.asm");
+    fs::write(&asm, PROGRAM).unwrap();
+    let _ = fs::remove_file(dir.join("prog.obj"));
+    let output = Command::new(env!("CARGO_BIN_EXE_risc-v-assembler"))
+        .arg(&asm)
+        .arg("--no-default-obj")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("`--no-default-obj` needs `--obj`"));
+    assert!(!dir.join("prog.obj").exists());
+    let status = Command::new(env!("CARGO_BIN_EXE_risc-v-assembler"))
+        .arg(&asm)
+        .arg("--no-default-obj")
+        .arg("--obj")
+        .arg(dir.join("out.obj"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn invalid_format() {
+    let output = Command::new(env!("CARGO_BIN_EXE_risc-v-assembler"))
+        .args(["main.asm", "--format", "xyz"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(
+        "Invalid Format: `xyz`, expected one of text, binary, rust-array, grouped-binary, coe"
+    ));
+}
+
+#[test]
+fn missing_files() {
+    let dir = std::env::temp_dir().join("risc-v-assembler-cli-missing");
+    fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("main.asm");
+    fs::write(&asm, ".include \"absent.asm\"\nnop\n").unwrap();
+    for path in [asm, dir.join("absent-main.asm")] {
+        let output = Command::new(env!("CARGO_BIN_EXE_risc-v-assembler"))
+            .arg(&path)
+            .arg("--obj")
+            .arg(dir.join("main.obj"))
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.starts_with("Error: "), "{}", stderr);
+        assert!(!stderr.contains("panicked"));
+    }
+}
+
+#[test]
+fn listing_over_input() {
+    let dir = std::env::temp_dir().join("risc-v-assembler-cli-listing-input");
+    fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("main.asm");
+    fs::write(&asm, PROGRAM).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_risc-v-assembler"))
+        .arg(&asm)
+        .arg("--obj")
+        .arg(dir.join("main.obj"))
+        .arg("--listing")
+        .arg(&asm)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("is the input file"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&asm).unwrap(), PROGRAM);
+}
+
+#[test]
+fn dry_run_addresses() {
+    let dir = std::env::temp_dir().join("risc-v-assembler-cli-dry-run");
+    fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("main.asm");
+    fs::write(&asm, "addi x5, x0, 1\nli x6, 0x12345\nadd x7, x5, x6\n").unwrap();
+    let map = dir.join("main.map");
+    let status = Command::new(env!("CARGO_BIN_EXE_risc-v-assembler"))
+        .arg(&asm)
+        .arg("--obj")
+        .arg(dir.join("main.obj"))
+        .arg("--dry-run-addresses")
+        .arg(&map)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let map = fs::read_to_string(map).unwrap();
+    let addresses: Vec<&str> = map
+        .lines()
+        .map(|line| line.rsplit(' ').next().unwrap())
+        .collect();
+    assert_eq!(addresses, ["0x00000000", "0x00000004", "0x0000000c"]);
+}
+
+#[test]
+fn rust_array_compiles() {
+    let dir = std::env::temp_dir().join("risc-v-assembler-cli-rust-array");
+    fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("main.asm");
+    fs::write(&asm, PROGRAM).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_risc-v-assembler"))
+        .arg(&asm)
+        .arg("--obj")
+        .arg(dir.join("boot.rs"))
+        .args(["--format", "rust-array", "--array-name", "BOOT"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    // Compile the generated source into a program that prints the array.
+    fs::write(
+        dir.join("check.rs"),
+        "include!(\"boot.rs\");\nfn main() {\n    print!(\"{} {:?}\", BOOT.len(), BOOT);\n}\n",
+    )
+    .unwrap();
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let status = Command::new(rustc)
+        .arg(dir.join("check.rs"))
+        .arg("-o")
+        .arg(dir.join("check"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = Command::new(dir.join("check")).output().unwrap();
+    let expected = assemble(PROGRAM).unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{} {:?}", expected.len(), expected)
+    );
+}
@@ -0,0 +1,39 @@
+use risc_v_assembler::assemble;
+
+/// Sum the 8-byte words at `a0` up to the first zero and store the total at
+/// `a1`.
+const SUM: &str = "
+        addi x5, x0, 0          // sum = 0
+Loop:
+        ld x6, 0(x10)
+        beq x6, x0, Done        // forward branch out of the loop
+        add x5, x5, x6
+        addi x10, x10, 8
+        blt x0, x6, Loop        // backward branch to the loop head
+Done:
+        sd x5, 0(x11)
+        j End
+        nop
+End:
+        nop
+";
+
+#[test]
+fn sum() {
+    // Branches use the processor's encoding: the distance in instructions in
+    // bits 11:7, with bit 25 set for a backward branch. Everything else is as
+    // reported by `llvm-mc -triple=riscv64 -show-encoding`.
+    let expected = [
+        0x00000293, // addi x5, x0, 0
+        0x00053303, // ld x6, 0(x10)
+        0x00030263, // beq x6, x0, +4
+        0x006282b3, // add x5, x5, x6
+        0x00850513, // addi x10, x10, 8
+        0x02604263, // blt x0, x6, -4
+        0x0055b023, // sd x5, 0(x11)
+        0x0080006f, // jal x0, +8
+        0x00000000, // nop
+        0x00000000, // nop
+    ];
+    assert_eq!(assemble(SUM).unwrap(), expected);
+}
@@ -0,0 +1,25 @@
+//! The library must build for targets without a filesystem or processes,
+//! such as `wasm32-unknown-unknown`, so only the binary may use them.
+
+use std::fs;
+
+#[test]
+fn library_avoids_fs_and_process() {
+    for entry in fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/src")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.file_name().unwrap() == "main.rs" {
+            continue;
+        }
+        let source = fs::read_to_string(&path).unwrap();
+        // Tests run natively, so they may use either.
+        let core = source.split("#[cfg(test)]").next().unwrap();
+        for forbidden in ["std::fs", "std::process", "panic!"] {
+            assert!(
+                !core.contains(forbidden),
+                "`{}` uses `{}`",
+                path.display(),
+                forbidden
+            );
+        }
+    }
+}